@@ -1,142 +1,1397 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
 use rand::Rng;
-use rand_distr::{Exp1, Uniform, Standard, Distribution};
+use rand_distr::{Exp1, Poisson, Uniform, Standard, Distribution};
 
+use crate::fitness::FitnessFunction;
 use crate::payoff_matrix::PayoffMatrix;
-use crate::lattice::{Lattice, LatticeIdx};
+use crate::lattice::{check_same_shape, Boundary, Lattice, LatticeIdx, ShapeError};
+use crate::neighborhood::Neighborhood;
+use crate::observer::{Change, Observer, Observers};
+use crate::rate_tree::RateTree;
+use crate::real::{widen, Real};
+use crate::regions::{PayoffRegions, RegionError};
+use crate::schedule::PayoffSchedule;
+use crate::transitions::TransitionRates;
+use crate::topology::{Grid, Neighbor, Topology};
+use crate::view::check_view_shape;
 
 /// Stores the state of the lattice, the fitness associated with each lattice
-/// point, and the time.
-#[derive(Debug)]
+/// point, and the time. Cells are reached through the [`Topology`] connecting
+/// them, and are identified by its node ids.
+///
+/// States take one byte per cell. Fitness takes the size of a [`Real`] per
+/// cell when it is stored, which is the default; large runs can instead
+/// compute it from the neighbors of a cell whenever it is needed. See
+/// [`BoneLattice::set_fitness_stored`]. The default [`Kinetics::Direct`]
+/// adds eight bytes per cell for its sums of rates, so only
+/// [`Kinetics::Scan`] with computed fitness stays at one byte per cell.
+#[derive(Debug, Clone)]
 pub struct BoneLattice {
-    data: Lattice<(State, f32)>,
-    pub time: f32,
+    /// Shared between clones, so replicates of a run store it once.
+    topology: Arc<dyn Topology>,
+    states: Vec<State>,
+    /// Fitness of each cell, or [`None`] if it is computed on demand.
+    fitness: Option<Vec<Real>>,
+    pub time: f64,
     payoff_matrix: PayoffMatrix,
+    /// Schedule the payoff matrix follows, if any.
+    schedule: Option<PayoffSchedule>,
+    /// Piece of the schedule in force.
+    piece: usize,
+    /// Matrices of the regions that do not use `payoff_matrix`, if any.
+    regions: Option<PayoffRegions>,
+    fitness_function: FitnessFunction,
+    /// Cells that take part in the simulation, or [`None`] if all of them do.
+    mask: Option<Vec<bool>>,
+    /// Number of cells in the domain.
+    domain_len: usize,
+    /// Number of cells in the domain in each state.
+    counts: [usize; 3],
+    /// Event rate of each cell unless using [`Kinetics::Scan`].
+    rates: Option<RateTree>,
+    /// Rate at which each cell invades without changing anything when using
+    /// [`Kinetics::RejectionFree`], which leaves these out of `rates`.
+    null_rates: Option<RateTree>,
+    /// Invasions skipped by [`Kinetics::RejectionFree`] so far.
+    null_events: u64,
+    update_rule: UpdateRule,
+    transitions: TransitionRates,
+    rate_policy: RatePolicy,
+    /// An invalid rate, which stops the simulation. Only the first one found
+    /// is kept, so other cells are not checked while there is one.
+    rate_error: Option<RateError>,
+    /// Whether the cell of the kept invalid rate became valid again, so that
+    /// every cell must be checked before the simulation goes on.
+    rescan_rates: bool,
+    observers: Observers,
+}
+
+/// Fewest expected events a tau leap is taken for; below this,
+/// [`BoneLattice::leap`] takes an exact step instead.
+const MIN_LEAP_EVENTS: f64 = 10.0;
+
+/// A change made by the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// `source` passed its state on to `target`: by invading it under
+    /// birth–death updates, or by being copied under other update rules.
+    /// The target is [`None`] if the invasion reached no cell that could
+    /// change, such as a fixed boundary site or one past an absorbing
+    /// boundary.
+    Invasion { source: usize, target: Option<usize>, state: State },
+    /// `node` changed to `state` on its own.
+    Transition { node: usize, state: State },
+}
+
+/// A cell whose stored values differ from freshly computed ones, found by
+/// [`BoneLattice::check_fitness`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Divergence {
+    pub node: usize,
+    /// Stored fitness, if fitness is stored.
+    pub stored_fitness: Option<Real>,
+    pub fitness: Real,
+    /// Stored event rate, if the kinetics keep rates.
+    pub stored_rate: Option<Real>,
+    pub rate: Real,
+}
+
+/// What a cell does when it fires.
+enum Action {
+    Invade(Neighbor),
+    Transition(State),
+}
+
+/// What happened during a call to [`BoneLattice::leap`].
+#[derive(Debug, Clone)]
+pub struct Leap {
+    /// Simulation time the leap covered.
+    pub tau: f64,
+    /// Whether the leap was a single exact step.
+    pub exact: bool,
+    /// The events of the leap, in the order they were drawn.
+    pub events: Vec<Event>,
+}
+
+/// How a step of the simulation changes the state of a cell.
+///
+/// Under birth–death updates cells reproduce at a rate given by their fitness.
+/// Under every other rule each cell in the domain is chosen to update at rate
+/// 1, and the rule decides which state it takes on. Boundary sites never take
+/// part in the competitions these rules hold.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UpdateRule {
+    /// A cell chosen in proportion to its fitness invades a neighbor chosen
+    /// uniformly at random.
+    #[default]
+    BirthDeath,
+    /// The chosen cell dies and is replaced by the offspring of a neighbor
+    /// chosen in proportion to fitness.
+    DeathBirth,
+    /// The chosen cell copies the state of itself or a neighbor, chosen in
+    /// proportion to fitness.
+    Imitation,
+    /// The chosen cell compares itself with a neighbor chosen uniformly at
+    /// random and copies its state with probability
+    /// `1 / (1 + exp((own - neighbor) / temperature))`, where the arguments
+    /// are the fitness of each. The temperature must be positive and finite.
+    Fermi { temperature: Real },
+}
+
+/// What happens to cells with low fitness where fitness is used as a rate:
+/// under birth–death updates, where it is the rate at which a cell invades,
+/// and under death–birth and imitation updates, where it weighs the
+/// competitors for a cell. A rate of 0 means a cell never spreads this way.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RatePolicy {
+    /// Fitness below `min` is raised to it, so every cell keeps some chance
+    /// of spreading. `min` must be finite and non-negative.
+    Clamp { min: Real },
+    /// Cells with negative fitness never spread.
+    Inactive,
+    /// Negative fitness is an error, returned by the next step.
+    #[default]
+    Reject,
+}
+
+impl RatePolicy {
+    /// Gets the rate a fitness gives under this policy. Negative fitness
+    /// gives 0 when rejected, since it is never used.
+    pub fn rate(self, fitness: Real) -> Real {
+        match self {
+            RatePolicy::Clamp { min } => fitness.max(min),
+            RatePolicy::Inactive | RatePolicy::Reject => fitness.max(0.0),
+        }
+    }
+}
+
+/// How [`BoneLattice::step`] chooses the cell that fires next, by invading or
+/// changing state on its own. Both methods give the same distribution of
+/// trajectories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Kinetics {
+    /// Draws an exponential waiting time for every cell and takes the
+    /// soonest, which takes O(N) time per step.
+    Scan,
+    /// Gillespie's direct method, which keeps the event rates in a sum tree so
+    /// that each step takes O(log N) time. The sums are kept in `f64` so that
+    /// small rates are not lost next to large totals, which takes eight bytes
+    /// per cell: with stored `f32` fitness, about 13 bytes per cell in all.
+    #[default]
+    Direct,
+    /// The direct method, but counting only invasions of neighbors in another
+    /// state, so that no step is spent on an invasion that changes nothing.
+    /// Time advances by the same amount as it would have over the skipped
+    /// invasions, which are counted by [`BoneLattice::null_events`]. This
+    /// helps most late in coarsening, when few neighbors differ. Skipped
+    /// invasions take another eight bytes per cell to track, and only
+    /// birth–death updates have any to skip.
+    RejectionFree,
 }
 
 impl BoneLattice {
-    /// Constructs a new lattice with the specified side length.
+    /// Constructs a new lattice with the specified extent along each axis, the
+    /// boundary condition of each axis and the neighborhood of every cell.
+    /// See [`Grid::new`] for how the neighborhood is checked.
     pub fn new<F: FnMut(LatticeIdx) -> State>(
-        size: i16, matrix:
-        PayoffMatrix,
+        shape: LatticeIdx,
+        matrix: PayoffMatrix,
+        boundaries: [Boundary<State>; 3],
+        neighborhood: Neighborhood,
         mut filler: F
-    ) -> Self {
+    ) -> Result<Self, InitError> {
+        let grid = Grid::new(shape, boundaries, neighborhood)?;
+        let states: Vec<_> = (0..grid.len())
+            .map(|node| filler(grid.idx(node)))
+            .collect();
+        Self::with_topology(Arc::new(grid), matrix, |node| states[node])
+    }
+
+    /// Constructs a new simulation on an arbitrary topology, calling `filler`
+    /// with each node id to get its initial state.
+    pub fn with_topology<F: FnMut(usize) -> State>(
+        topology: Arc<dyn Topology>,
+        matrix: PayoffMatrix,
+        mut filler: F
+    ) -> Result<Self, InitError> {
+        if topology.is_empty() {
+            return Err(InitError::NoNodes);
+        }
+
+        let len = topology.len();
         let mut this = Self {
-            data: Lattice::filled(size, |idx| (filler(idx), 0.0)),
+            states: (0..len).map(&mut filler).collect(),
+            fitness: Some(vec![0.0; len]),
+            topology,
             time: 0.0,
             payoff_matrix: matrix,
+            schedule: None,
+            piece: 0,
+            regions: None,
+            fitness_function: FitnessFunction::default(),
+            mask: None,
+            domain_len: len,
+            counts: [0; 3],
+            rates: Some(RateTree::new(len)),
+            null_rates: None,
+            null_events: 0,
+            update_rule: UpdateRule::default(),
+            transitions: TransitionRates::default(),
+            rate_policy: RatePolicy::default(),
+            rate_error: None,
+            rescan_rates: false,
+            observers: Observers::default(),
         };
 
         // Generate initial fitness for every value
-        for idx in LatticeIdx::cube_iter(this.size()) {
-            this.gen_fitness(idx);
+        this.gen_all_fitness();
+
+        Ok(this)
+    }
+
+    /// Constructs a new simulation on an arbitrary topology with the state of
+    /// every node drawn from `rng`.
+    pub fn random<R: Rng + ?Sized>(
+        topology: Arc<dyn Topology>,
+        matrix: PayoffMatrix,
+        rng: &mut R
+    ) -> Result<Self, InitError> {
+        Self::with_topology(topology, matrix, |_| rng.gen())
+    }
+
+    /// Starts the simulation over at time 0 with the state of every node drawn
+    /// from `rng` as in [`BoneLattice::random`], keeping the topology, payoff
+    /// matrix, domain, observers and other settings. Observers start sampling
+    /// again from time 0, and a payoff schedule starts over with them.
+    pub fn restart<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        for state in &mut self.states {
+            *state = rng.gen();
+        }
+        self.time = 0.0;
+        self.null_events = 0;
+        self.observers.restart(self.time);
+        if let Some(ref schedule) = self.schedule {
+            self.piece = schedule.piece_at(self.time);
+            self.payoff_matrix = schedule.matrix(self.piece).clone();
+        }
+        self.gen_all_fitness();
+    }
+
+    /// Chooses between storing the fitness of every cell, which is faster,
+    /// and computing it from the neighbors of a cell every time it is needed,
+    /// which saves the size of a [`Real`] per cell.
+    pub fn set_fitness_stored(&mut self, stored: bool) {
+        match (stored, self.fitness.is_some()) {
+            (true, false) => {
+                self.fitness = Some(vec![0.0; self.len()]);
+                self.gen_all_fitness();
+            },
+            (false, true) => self.fitness = None,
+            _ => {},
+        }
+    }
+
+    /// Whether the fitness of every cell is stored rather than computed on
+    /// demand.
+    pub fn fitness_stored(&self) -> bool {
+        self.fitness.is_some()
+    }
+
+    /// Chooses how the next invasion is found; see [`Kinetics`].
+    pub fn set_kinetics(&mut self, kinetics: Kinetics) {
+        if kinetics == self.kinetics() { return }
+        self.rates = match kinetics {
+            Kinetics::Scan => None,
+            _ => Some(RateTree::new(self.len())),
+        };
+        self.null_rates = match kinetics {
+            Kinetics::RejectionFree => Some(RateTree::new(self.len())),
+            _ => None,
+        };
+        self.gen_all_fitness();
+    }
+
+    /// Changes how payoffs turn into fitness, which is their sum unless set
+    /// otherwise, and regenerates fitness to match.
+    pub fn set_fitness_function(&mut self, function: FitnessFunction) {
+        self.fitness_function = function;
+        self.gen_all_fitness();
+    }
+
+    pub fn fitness_function(&self) -> FitnessFunction {
+        self.fitness_function
+    }
+
+    /// Gets the payoff matrix in force, which every cell plays by except in
+    /// regions with their own; see [`BoneLattice::payoff_matrix_of`].
+    pub fn payoff_matrix(&self) -> &PayoffMatrix {
+        &self.payoff_matrix
+    }
+
+    /// Makes the payoff matrix follow a schedule over simulation time,
+    /// starting with the matrix it gives for the current time, and
+    /// regenerates fitness to match. Without a schedule, the matrix in force
+    /// is kept from then on.
+    pub fn set_payoff_schedule(&mut self, schedule: Option<PayoffSchedule>) {
+        self.schedule = schedule;
+        if let Some(ref schedule) = self.schedule {
+            self.piece = schedule.piece_at(self.time);
+            self.payoff_matrix = schedule.matrix(self.piece).clone();
+            self.gen_all_fitness();
+        }
+    }
+
+    pub fn payoff_schedule(&self) -> Option<&PayoffSchedule> {
+        self.schedule.as_ref()
+    }
+
+    /// Gives regions of the simulation their own payoff matrices, or makes
+    /// every cell use the lattice's matrix again, and regenerates fitness to
+    /// match. Fails if the regions do not cover every node.
+    pub fn set_payoff_regions(&mut self, regions: Option<PayoffRegions>) -> Result<(), RegionError> {
+        if let Some(ref regions) = regions {
+            if regions.len() != self.len() {
+                return Err(RegionError::Len { len: regions.len(), expected: self.len() });
+            }
+        }
+        self.regions = regions;
+        self.gen_all_fitness();
+        Ok(())
+    }
+
+    pub fn payoff_regions(&self) -> Option<&PayoffRegions> {
+        self.regions.as_ref()
+    }
+
+    /// Gets the payoff matrix a cell plays by.
+    pub fn payoff_matrix_of(&self, node: usize) -> &PayoffMatrix {
+        self.regions.as_ref()
+            .and_then(|regions| regions.matrix(node))
+            .unwrap_or(&self.payoff_matrix)
+    }
+
+    /// Time the payoff schedule next changes, which is infinite without one.
+    fn next_breakpoint(&self) -> f64 {
+        self.schedule.as_ref()
+            .and_then(|schedule| schedule.start(self.piece + 1))
+            .unwrap_or(f64::INFINITY)
+    }
+
+    /// Moves time to the next breakpoint of the payoff schedule and switches
+    /// to the matrix after it, regenerating fitness if it changed.
+    /// Draws how many invasions that change nothing the other kinetics would
+    /// have taken over some time, which is Poisson since their rates stay
+    /// the same until the next event.
+    fn count_null_events<R: Rng + ?Sized>(&mut self, elapsed: f64, rng: &mut R) {
+        let Some(ref null_rates) = self.null_rates else { return };
+        let mean = null_rates.total() * elapsed;
+        if mean > 0.0 && mean.is_finite() {
+            self.null_events += rng.sample(Poisson::new(mean).expect("mean is positive")) as u64;
+        }
+    }
+
+    fn cross_breakpoint(&mut self, breakpoint: f64) {
+        self.advance(breakpoint - self.time);
+        self.time = breakpoint;
+        self.piece += 1;
+        let schedule = self.schedule.as_ref().expect("breakpoints come from the schedule");
+        let matrix = schedule.matrix(self.piece);
+        if *matrix != self.payoff_matrix {
+            self.payoff_matrix = matrix.clone();
+            self.gen_all_fitness();
+        }
+    }
+
+    /// Chooses how steps change the state of a cell; see [`UpdateRule`].
+    /// Fails if the temperature of [`UpdateRule::Fermi`] is not positive and
+    /// finite, which would make adoption NaN or certain.
+    pub fn set_update_rule(&mut self, rule: UpdateRule) -> Result<(), SettingError> {
+        if let UpdateRule::Fermi { temperature } = rule {
+            if !(temperature.is_finite() && temperature > 0.0) {
+                return Err(SettingError::Temperature { temperature });
+            }
+        }
+        self.update_rule = rule;
+        self.gen_all_fitness();
+        Ok(())
+    }
+
+    /// Chooses what happens to cells with low fitness; see [`RatePolicy`].
+    /// Fails if the minimum of [`RatePolicy::Clamp`] is negative, infinite or
+    /// NaN, which would give invalid rates.
+    pub fn set_rate_policy(&mut self, policy: RatePolicy) -> Result<(), SettingError> {
+        if let RatePolicy::Clamp { min } = policy {
+            if !(min.is_finite() && min >= 0.0) {
+                return Err(SettingError::ClampMinimum { min });
+            }
+        }
+        self.rate_policy = policy;
+        self.gen_all_fitness();
+        Ok(())
+    }
+
+    pub fn rate_policy(&self) -> RatePolicy {
+        self.rate_policy
+    }
+
+    /// Attaches an observer, which is told about every event from now on and,
+    /// if `interval` is given, samples the lattice every `interval` units of
+    /// simulation time starting now. Clones of the lattice do not keep it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is not positive.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>, interval: Option<f64>) {
+        assert!(interval.is_none_or(|interval| interval > 0.0), "sampling interval must be positive");
+        self.observers.add(observer, interval, self.time);
+    }
+
+    /// Detaches every observer, returning them in the order they were added.
+    pub fn take_observers(&mut self) -> Vec<Box<dyn Observer>> {
+        self.observers.take()
+    }
+
+    /// Tells the observers about a change that was just made.
+    fn notify(&mut self, changes: &[Change]) {
+        if self.observers.is_empty() { return }
+        let mut observers = std::mem::take(&mut self.observers);
+        for change in changes {
+            observers.event(self, change);
+        }
+        self.observers = observers;
+    }
+
+    /// Advances time to the next event, first letting the observers sample
+    /// the lattice as it is until then.
+    fn advance(&mut self, wait: f64) {
+        if !self.observers.is_empty() {
+            let mut observers = std::mem::take(&mut self.observers);
+            observers.sample_until(self, self.time + wait);
+            self.observers = observers;
+        }
+        self.time += wait;
+    }
+
+    /// Gets the invalid rate that stops [`BoneLattice::step`], if any.
+    pub fn rate_error(&self) -> Option<RateError> {
+        match self.rescan_rates {
+            true => self.find_rate_error(),
+            false => self.rate_error,
+        }
+    }
+
+    /// Checks the rate of every cell if the kept invalid rate became valid,
+    /// since other cells were not checked while it was kept.
+    fn settle_rate_error(&mut self) -> Result<(), RateError> {
+        if self.rescan_rates {
+            self.rate_error = self.find_rate_error();
+            self.rescan_rates = false;
+        }
+        match self.rate_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Finds the first cell with an invalid rate, if any.
+    fn find_rate_error(&self) -> Option<RateError> {
+        (0..self.len()).find_map(|node| self.check_rate(node, self.fitness(node)).err())
+    }
+
+    /// Checks the rate of a cell whose fitness changed, keeping it if it is
+    /// the first invalid one or clearing the kept one if it became valid.
+    fn note_rate(&mut self, node: usize, fitness: Real) {
+        match self.rate_error {
+            None => self.rate_error = self.check_rate(node, fitness).err(),
+            Some(err) if err.node() == node => {
+                self.rate_error = self.check_rate(node, fitness).err();
+                self.rescan_rates |= self.rate_error.is_none();
+            },
+            Some(_) => {},
+        }
+    }
+
+    pub fn update_rule(&self) -> UpdateRule {
+        self.update_rule
+    }
+
+    /// Sets the rates at which cells change state on their own. These events
+    /// are scheduled alongside the others, whatever the update rule.
+    pub fn set_transition_rates(&mut self, transitions: TransitionRates) {
+        self.transitions = transitions;
+        self.gen_all_fitness();
+    }
+
+    pub fn transition_rates(&self) -> TransitionRates {
+        self.transitions
+    }
+
+    pub fn kinetics(&self) -> Kinetics {
+        match (&self.rates, &self.null_rates) {
+            (Some(_), Some(_)) => Kinetics::RejectionFree,
+            (Some(_), None) => Kinetics::Direct,
+            (None, _) => Kinetics::Scan,
+        }
+    }
+
+    /// Number of invasions that would have changed nothing which
+    /// [`Kinetics::RejectionFree`] has skipped over since the lattice was
+    /// created or restarted. These are drawn as they would have happened over
+    /// all the time the simulation covers, including leaps and waits cut
+    /// short by the payoff schedule, so adding them to the events taken
+    /// gives the events another kinetics would have needed.
+    pub fn null_events(&self) -> u64 {
+        self.null_events
+    }
+
+    pub fn topology(&self) -> &dyn Topology {
+        &*self.topology
+    }
+
+    /// Gets the lattice the simulation runs on, or [`None`] if its topology
+    /// is not a lattice.
+    pub fn grid(&self) -> Option<&Grid> {
+        self.topology.as_grid()
+    }
+
+    fn grid_or_err(&self) -> Result<&Grid, ShapeError> {
+        self.grid().ok_or(ShapeError::NotALattice)
+    }
+
+    /// Number of cells, including those outside the domain.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn state(&self, node: usize) -> &State {
+        &self.states[node]
+    }
+
+    /// Sets the state of a cell and regenerates the fitness of the cells
+    /// affected.
+    pub fn set_state(&mut self, node: usize, state: State) {
+        self.write_state(node, state);
+        self.refresh_around(node);
+    }
+
+    /// Sets the state of a cell, keeping the counts of each state up to date
+    /// but leaving fitness alone.
+    fn write_state(&mut self, node: usize, state: State) {
+        if self.in_domain(node) {
+            self.counts[self.states[node] as usize] -= 1;
+            self.counts[state as usize] += 1;
+        }
+        self.states[node] = state;
+    }
+
+    /// Restricts the simulation to the cells where `mask` is true, or lifts
+    /// the restriction if it is [`None`]. Cells outside the domain are inert:
+    /// they never invade or get invaded, are not counted, and neighbor them
+    /// like an absorbing boundary. Fails if the simulation does not run on a
+    /// lattice or if the mask has a different shape.
+    pub fn set_mask(&mut self, mask: Option<Lattice<bool>>) -> Result<(), ShapeError> {
+        let mask: Option<Vec<bool>> = match mask {
+            Some(mask) => {
+                let grid = self.grid_or_err()?;
+                check_same_shape(grid.shape(), mask.shape())?;
+                Some((0..self.len()).map(|node| mask[grid.idx(node)]).collect())
+            },
+            None => None,
+        };
+        self.domain_len = match mask {
+            Some(ref mask) => mask.iter().filter(|&&cell| cell).count(),
+            None => self.len(),
+        };
+        self.mask = mask;
+
+        self.gen_all_fitness();
+        Ok(())
+    }
+
+    /// Whether a cell is part of the simulation domain.
+    pub fn in_domain(&self, node: usize) -> bool {
+        self.mask.as_ref().is_none_or(|mask| mask[node])
+    }
+
+    /// Calls `f` on every neighbor of a cell. Cells outside the domain are
+    /// treated as void.
+    pub fn for_each_neighbor<F: FnMut(Neighbor)>(&self, node: usize, mut f: F) {
+        self.topology.for_each_neighbor(node, &mut |neighbor| f(self.masked(neighbor)))
+    }
+
+    fn masked(&self, neighbor: Neighbor) -> Neighbor {
+        match neighbor {
+            Neighbor::Node(node) if !self.in_domain(node) => Neighbor::Void,
+            neighbor => neighbor,
+        }
+    }
+
+    /// Copies the states of the box of the provided shape whose low corner is
+    /// at `low`, wrapping around the edges of the lattice. Cells outside the
+    /// domain are [`None`]. Fails if the simulation does not run on a lattice.
+    pub fn region(&self, low: LatticeIdx, shape: LatticeIdx)
+        -> Result<Lattice<Option<State>>, ShapeError>
+    {
+        let grid = self.grid_or_err()?;
+        check_view_shape(grid.shape(), shape)?;
+        Lattice::filled(shape, |idx| {
+            let node = grid.node(low.offset_within(idx, grid.shape()));
+            self.in_domain(node).then(|| *self.state(node))
+        })
+    }
+
+    /// Writes states into the box whose low corner is at `low`, which has the
+    /// shape of `states`, and regenerates fitness to match. Fails if the
+    /// simulation does not run on a lattice.
+    pub fn set_region(&mut self, low: LatticeIdx, states: &Lattice<State>) -> Result<(), ShapeError> {
+        let grid = self.grid_or_err()?;
+        check_view_shape(grid.shape(), states.shape())?;
+        let nodes: Vec<_> = LatticeIdx::shape_iter(states.shape())
+            .map(|idx| (grid.node(low.offset_within(idx, grid.shape())), states[idx]))
+            .collect();
+        for (node, state) in nodes {
+            self.write_state(node, state);
+        }
+
+        self.gen_all_fitness();
+        Ok(())
+    }
+
+    /// Regenerates the stored fitness and event rate of a cell by looking at
+    /// its neighbors, and checks that its rate is valid.
+    pub fn gen_fitness(&mut self, node: usize) {
+        let fitness = self.compute_fitness(node);
+        self.note_rate(node, fitness);
+        if self.fitness.is_none() && self.rates.is_none() { return }
+        let rate = self.event_rate(node, fitness);
+        let null_rate = self.null_rates.as_ref().map(|_| self.null_rate(node, fitness));
+        if let Some(ref mut stored) = self.fitness {
+            stored[node] = fitness;
+        }
+        if let Some(ref mut rates) = self.rates {
+            rates.set(node, rate);
+        }
+        if let (Some(null_rates), Some(null_rate)) = (&mut self.null_rates, null_rate) {
+            null_rates.set(node, null_rate);
+        }
+    }
+
+    /// Regenerates the stored fitness of every cell and counts the cells in
+    /// each state.
+    fn gen_all_fitness(&mut self) {
+        self.counts = [0; 3];
+        for node in 0..self.len() {
+            if self.in_domain(node) {
+                self.counts[self.states[node] as usize] += 1;
+            }
+        }
+
+        self.rate_error = None;
+        self.rescan_rates = false;
+        for node in 0..self.len() {
+            self.gen_fitness(node);
+        }
+    }
+
+    /// Checks that the fitness of a cell gives a valid rate: it must be
+    /// finite, and it must not be negative if the [`RatePolicy`] rejects
+    /// negative rates and the update rule uses fitness as a rate.
+    fn check_rate(&self, node: usize, fitness: Real) -> Result<(), RateError> {
+        if !self.in_domain(node) {
+            return Ok(());
+        }
+        if !fitness.is_finite() {
+            return Err(RateError::NonFinite { node, fitness, time: self.time });
+        }
+        let used = !matches!(self.update_rule, UpdateRule::Fermi { .. });
+        if used && fitness < 0.0 && self.rate_policy == RatePolicy::Reject {
+            return Err(RateError::Negative { node, fitness, time: self.time });
+        }
+        Ok(())
+    }
+
+    /// Computes the fitness of a cell by looking at its neighbors, ignoring
+    /// any stored value.
+    pub fn compute_fitness(&self, node: usize) -> Real {
+
+        if !self.in_domain(node) {
+            return 0.0;
+        }
+
+        let current_state = *self.state(node);
+        let matrix = self.payoff_matrix_of(node);
+
+        // Absorbing boundaries contribute nothing to the fitness
+        let mut total = 0.0;
+        let mut played = 0;
+        self.for_each_neighbor(node, |neighbor| {
+            let against = match neighbor {
+                Neighbor::Node(neighbor) => *self.state(neighbor),
+                Neighbor::Fixed(state) => state,
+                Neighbor::Void => return,
+            };
+            total += matrix.get(current_state, against);
+            played += 1;
+        });
+
+        self.fitness_function.fitness(total, played)
+    }
+
+    /// Returns the stored fitness of a cell, or [`None`] if fitness is
+    /// computed on demand.
+    pub fn stored_fitness(&self, node: usize) -> Option<Real> {
+        self.fitness.as_ref().map(|fitness| fitness[node])
+    }
+
+    /// Returns the fitness of a cell, computing it if it is not stored.
+    pub fn fitness(&self, node: usize) -> Real {
+        self.stored_fitness(node).unwrap_or_else(|| self.compute_fitness(node))
+    }
+
+    /// Recomputes the fitness and event rate of every cell and returns the
+    /// cells where a stored value differs from the recomputed one. Stored
+    /// values are kept up to date incrementally, so any difference is a bug.
+    pub fn check_fitness(&self) -> Vec<Divergence> {
+        let mut divergences = Vec::new();
+        for node in 0..self.len() {
+            let fitness = self.compute_fitness(node);
+            let rate = self.event_rate(node, fitness);
+            let stored_fitness = self.stored_fitness(node);
+            let stored_rate = self.rates.as_ref().map(|rates| rates.rate(node));
+
+            // The same computation always gives the same bits, but rates are
+            // read back from running sums, which round
+            let differs = |stored: Option<Real>, computed: Real| {
+                stored.is_some_and(|stored| stored.to_bits() != computed.to_bits())
+            };
+            let rate_differs = self.rates.as_ref().is_some_and(|rates| !rates.holds(node, rate));
+            if differs(stored_fitness, fitness) || rate_differs {
+                divergences.push(Divergence { node, stored_fitness, fitness, stored_rate, rate });
+            }
+        }
+        divergences
+    }
+
+    /// Performs one time step in the simulation with randomness drawn from
+    /// `rng`, returning what changed, or [`None`] if nothing can ever change
+    /// again, in which case time becomes infinite. Fails without changing
+    /// anything if a cell has an invalid rate, which stays the case until
+    /// the cell or the settings making it invalid change.
+    ///
+    /// When the payoff schedule changes before the next event, time stops at
+    /// the breakpoint, every rate changes, and the wait for the next event
+    /// starts over, which is exact since waits are memoryless. A step can
+    /// cross any number of breakpoints, and fails at one that makes a rate
+    /// invalid.
+    pub fn step<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Result<Option<Event>, RateError> {
+        // Crossings with nothing able to fire, which can only go on for one
+        // period of the schedule before repeating
+        let mut idle = 0;
+        loop {
+            self.settle_rate_error()?;
+
+            // Cells fire at the rates kept by the kinetics, which cover
+            // invasions under birth-death updates and spontaneous transitions
+            let next = match self.rates {
+                Some(_) => self.next_direct(rng),
+                None => self.next_scan(rng),
+            };
+
+            // Under other update rules, every cell in the domain also updates
+            // at rate 1
+            let update = match self.update_rule {
+                UpdateRule::BirthDeath => None,
+                _ if self.domain_len == 0 => None,
+                rule => Some((rule, rng.sample::<f64, _>(Exp1) / self.domain_len as f64)),
+            };
+
+            let wait = next.map_or(f64::INFINITY, |(_, time)| time)
+                .min(update.map_or(f64::INFINITY, |(_, time)| time));
+            let breakpoint = self.next_breakpoint();
+            if breakpoint.is_finite() && self.time + wait >= breakpoint {
+                if wait.is_infinite() {
+                    idle += 1;
+                    if idle > self.schedule.as_ref().map_or(0, PayoffSchedule::len) {
+                        self.time = f64::INFINITY;
+                        return Ok(None);
+                    }
+                }
+                self.count_null_events(breakpoint - self.time, rng);
+                self.cross_breakpoint(breakpoint);
+                continue;
+            }
+            self.count_null_events(wait, rng);
+
+            return match (next, update) {
+                (Some((node, time)), None) => {
+                    self.advance(time);
+                    Ok(Some(self.fire(node, rng)))
+                },
+                (Some((node, time)), Some((_, update_time))) if time < update_time => {
+                    self.advance(time);
+                    Ok(Some(self.fire(node, rng)))
+                },
+                (_, Some((rule, update_time))) => {
+                    self.advance(update_time);
+                    Ok(Some(self.update(rule, rng)))
+                },
+                (None, None) => {
+                    self.time = f64::INFINITY;
+                    Ok(None)
+                },
+            };
+        }
+    }
+
+    /// Rate at which a cell fires, given its fitness: invasions under
+    /// birth–death updates, plus spontaneous transitions out of its state.
+    /// Cells with negative fitness never invade.
+    fn event_rate(&self, node: usize, fitness: Real) -> Real {
+        if !self.in_domain(node) {
+            return 0.0;
+        }
+        self.birth_rate(node, fitness) + self.transitions.total(*self.state(node))
+    }
+
+    /// Rate at which a cell invades, given its fitness. With
+    /// [`Kinetics::RejectionFree`], this only counts invasions of neighbors
+    /// in another state, of which a cell without neighbors has none.
+    fn birth_rate(&self, node: usize, fitness: Real) -> Real {
+        let birth = match self.update_rule {
+            UpdateRule::BirthDeath => self.rate_policy.rate(fitness),
+            _ => return 0.0,
+        };
+        match (&self.null_rates, self.topology.degree(node)) {
+            (Some(_), 0) => 0.0,
+            (Some(_), degree) => birth * self.discordant(node) as Real / degree as Real,
+            (None, _) => birth,
         }
+    }
 
-        this
+    /// Rate at which a cell invades without changing anything, given its
+    /// fitness: invasions of neighbors in the same state, of fixed boundary
+    /// cells and past absorbing boundaries. Cells without neighbors are left
+    /// out, so that they never fire.
+    fn null_rate(&self, node: usize, fitness: Real) -> Real {
+        let degree = self.topology.degree(node);
+        match self.update_rule {
+            UpdateRule::BirthDeath if self.in_domain(node) && degree > 0 => {
+                let null = degree - self.discordant(node);
+                self.rate_policy.rate(fitness) * null as Real / degree as Real
+            },
+            _ => 0.0,
+        }
     }
 
-    pub fn size(&self) -> i16 {
-        self.data.size
+    /// Number of neighbors of a cell in another state, counting a neighbor
+    /// again for every time it appears.
+    fn discordant(&self, node: usize) -> usize {
+        let state = *self.state(node);
+        let mut count = 0;
+        self.for_each_neighbor(node, |neighbor| {
+            if let Neighbor::Node(other) = neighbor {
+                count += (*self.state(other) != state) as usize;
+            }
+        });
+        count
     }
 
-    pub fn state(&self, idx: LatticeIdx) -> &State {
-        &self.data[idx].0
+    /// Chooses a neighbor in another state uniformly at random for a cell to
+    /// invade, or returns [`Neighbor::Void`] if there are none.
+    fn choose_discordant<R: Rng + ?Sized>(&self, node: usize, rng: &mut R) -> Neighbor {
+        let state = *self.state(node);
+        let mut discordant = Vec::with_capacity(self.topology.degree(node));
+        self.for_each_neighbor(node, |neighbor| {
+            if let Neighbor::Node(other) = neighbor {
+                if *self.state(other) != state {
+                    discordant.push(neighbor);
+                }
+            }
+        });
+        match discordant.len() {
+            0 => Neighbor::Void,
+            len => discordant[rng.sample(Uniform::new(0, len))],
+        }
     }
 
-    pub fn state_mut(&mut self, idx: LatticeIdx) -> &mut State {
-        &mut self.data[idx].0
+    /// Decides whether a cell that fires invades a neighbor or changes state
+    /// on its own, in proportion to the rates of each.
+    fn choose_action<R: Rng + ?Sized>(&self, node: usize, rng: &mut R) -> Action {
+        let state = *self.state(node);
+        let birth = self.birth_rate(node, self.fitness(node));
+        let total = birth + self.transitions.total(state);
+        if rng.gen::<Real>() * total < birth {
+            match self.null_rates {
+                Some(_) => Action::Invade(self.choose_discordant(node, rng)),
+                None => Action::Invade(self.choose_target(node, rng)),
+            }
+        } else {
+            Action::Transition(self.transitions.choose(state, rng))
+        }
+    }
+
+    /// Carries out the event of a cell chosen by the kinetics.
+    fn fire<R: Rng + ?Sized>(&mut self, node: usize, rng: &mut R) -> Event {
+        let target = match self.choose_action(node, rng) {
+            Action::Invade(target) => target,
+            Action::Transition(state) => {
+                let old = *self.state(node);
+                self.write_state(node, state);
+                self.refresh_around(node);
+                self.notify(&[Change { time: self.time, source: None, target: node, old, new: state }]);
+                return Event::Transition { node, state };
+            },
+        };
+
+        // Invade the neighbor; fixed boundary cells cannot be invaded, and
+        // invasions past an absorbing boundary are lost
+        let invasion_state = *self.state(node);
+        let target = match target {
+            Neighbor::Node(target) => Some(target),
+            _ => None,
+        };
+        if let Some(target) = target {
+            let old = *self.state(target);
+            if old != invasion_state {
+                self.write_state(target, invasion_state);
+                self.refresh_around(target);
+            }
+            self.notify(&[Change { time: self.time, source: Some(node), target, old, new: invasion_state }]);
+        }
+
+        // Return info about what was changed
+        Event::Invasion { source: node, target, state: invasion_state }
     }
 
-    /// Computes the fitness of a cell at a particular index by looking at its
-    /// neighbors
-    pub fn gen_fitness(&mut self, idx: LatticeIdx) {
+    /// Performs an update under a rule other than birth–death, where a cell
+    /// chosen uniformly at random from the domain takes on a new state. If it
+    /// keeps its own state, it is returned as the source.
+    fn update<R: Rng + ?Sized>(&mut self, rule: UpdateRule, rng: &mut R) -> Event {
+        let focal = loop {
+            let node = rng.gen_range(0..self.len());
+            if self.in_domain(node) { break node }
+        };
+
+        let source = match rule {
+            UpdateRule::BirthDeath => unreachable!("birth-death steps choose the invader first"),
+            UpdateRule::DeathBirth => self.compete(focal, false, rng),
+            UpdateRule::Imitation => self.compete(focal, true, rng),
+            UpdateRule::Fermi { temperature } => match self.choose_target(focal, rng) {
+                Neighbor::Node(neighbor) => {
+                    let difference = self.fitness(focal) - self.fitness(neighbor);
+                    let adopt = 1.0 / (1.0 + (difference / temperature).exp());
+                    (rng.gen::<Real>() < adopt).then_some(neighbor)
+                },
+                _ => None,
+            },
+        };
 
-        let current_state = *self.state(idx);
+        let source = source.unwrap_or(focal);
+        let state = *self.state(source);
+        let old = *self.state(focal);
+        if state != old {
+            self.write_state(focal, state);
+            self.refresh_around(focal);
+        }
+        self.notify(&[Change { time: self.time, source: Some(source), target: focal, old, new: state }]);
+        Event::Invasion { source, target: Some(focal), state }
+    }
 
-        let fitness = LatticeIdx::neighbor_iter(idx)
-            .map(|neighbor| {
-                self.payoff_matrix.get(current_state, *self.state(neighbor))
-            })
-            .sum();
+    /// Chooses a neighbor of a cell, or the cell itself if `include_self` is
+    /// set, with probability proportional to fitness. Returns [`None`] if none
+    /// of them has positive fitness.
+    fn compete<R: Rng + ?Sized>(&self, node: usize, include_self: bool, rng: &mut R) -> Option<usize> {
+        let mut competitors = Vec::with_capacity(self.topology.degree(node) + 1);
+        if include_self {
+            competitors.push((node, self.rate_policy.rate(self.fitness(node))));
+        }
+        self.for_each_neighbor(node, |neighbor| {
+            if let Neighbor::Node(neighbor) = neighbor {
+                competitors.push((neighbor, self.rate_policy.rate(self.fitness(neighbor))));
+            }
+        });
 
-        *self.fitness_mut(idx) = fitness;
+        let total: Real = competitors.iter().map(|&(_, fitness)| fitness).sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut target = rng.gen::<Real>() * total;
+        for &(competitor, fitness) in &competitors {
+            if target < fitness && fitness > 0.0 {
+                return Some(competitor);
+            }
+            target -= fitness;
+        }
+
+        // Rounding can carry the target past the last competitor
+        competitors.iter().rev().find(|&&(_, fitness)| fitness > 0.0).map(|&(node, _)| node)
     }
 
-    /// Returns the stored fitness of a cell at a particular point.
-    pub fn stored_fitness(&self, idx: LatticeIdx) -> &f32 {
-        &self.data[idx].1
+    /// Regenerates fitness for a cell whose state changed and for every cell
+    /// that has it as a neighbor, which are the only cells whose fitness
+    /// depends on its state.
+    fn refresh_around(&mut self, node: usize) {
+        let mut dependents = Vec::with_capacity(self.topology.degree(node));
+        self.topology.for_each_reverse_neighbor(node, &mut |other| dependents.push(other));
+        self.gen_fitness(node);
+        for other in dependents {
+            self.gen_fitness(other);
+        }
     }
 
-    /// Returns the fitness of a cell at a particular point.
-    pub fn fitness_mut(&mut self, idx: LatticeIdx) -> &mut f32 {
-        &mut self.data[idx].1
+    /// Chooses a neighbor of a cell uniformly at random for it to invade.
+    /// Invasions of cells without neighbors are lost.
+    fn choose_target<R: Rng + ?Sized>(&self, node: usize, rng: &mut R) -> Neighbor {
+        match self.topology.degree(node) {
+            0 => Neighbor::Void,
+            degree => self.masked(self.topology.neighbor(
+                node, rng.sample(Uniform::new(0, degree)))),
+        }
     }
 
-    /// Performs one time step in the simulation.
-    #[must_use]
-    pub fn step(&mut self) -> (LatticeIdx, State) {
-        let mut rng = rand::thread_rng();
-        let mut min_time = f32::INFINITY;
-        let mut min_time_idx = LatticeIdx(0, 0, 0);
+    /// Finds the cell that fires next and the time until it does by drawing a
+    /// waiting time for every cell and taking the soonest.
+    fn next_scan<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(usize, f64)> {
+        let mut min_time = f64::INFINITY;
+        let mut min_time_node = 0;
 
         // Compute expected times of invasion based on each value's fitness, and
         // find the lowest
-        for idx in LatticeIdx::cube_iter(self.size()) {
-            let lambda = *self.stored_fitness(idx);
-            let time = rng.sample::<f32, _>(Exp1) / lambda;
-            //dbg!((lambda, time));
+        for node in 0..self.len() {
+            let lambda = self.event_rate(node, self.fitness(node));
+            let time = rng.sample::<f64, _>(Exp1) / widen(lambda);
             if time < min_time {
                 min_time = time;
-                min_time_idx = idx;
+                min_time_node = node;
             }
         }
 
-        // Choose a neighbor uniformly at random to invade
-        let invaded = match rng.sample::<u8, _>(Uniform::new(0, 6)) {
-            0 => LatticeIdx(-1, 0, 0),
-            1 => LatticeIdx(1, 0, 0),
-            2 => LatticeIdx(0, -1, 0),
-            3 => LatticeIdx(0, 1, 0),
-            4 => LatticeIdx(0, 0, -1),
-            5 => LatticeIdx(0, 0, 1),
-            _ => unreachable!(),
-        };
+        (min_time != f64::INFINITY).then_some((min_time_node, min_time))
+    }
 
-        // Invade the neighbor
-        let invasion_state = *self.state(min_time_idx);
-        *self.state_mut(min_time_idx + invaded) = invasion_state;
+    /// Finds the cell that fires next and the time until it does using
+    /// Gillespie's direct method. The soonest of independent exponential
+    /// clocks arrives after an exponential time with the total rate, and
+    /// belongs to each cell with probability proportional to its rate, so
+    /// this matches [`BoneLattice::next_scan`] in distribution.
+    fn next_direct<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Option<(usize, f64)> {
+        let node = self.sample_rates(rng)?;
+        let total = self.rates.as_ref().expect("direct method keeps rates").total();
+        let time = rng.sample::<f64, _>(Exp1) / total;
+        Some((node, time))
+    }
 
-        // Regenerate fitness for the neighbors surrounded
-        for idx in LatticeIdx::neighbor_iter(min_time_idx) {
-            self.gen_fitness(idx);
+    /// Chooses a cell with probability proportional to its event rate, or
+    /// returns [`None`] if no cell can fire.
+    fn sample_rates<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Option<usize> {
+        let rates = self.rates.as_mut().expect("direct method keeps rates");
+        loop {
+            let total = rates.total();
+            if total <= 0.0 {
+                return None;
+            }
+
+            if let Some(node) = rates.find(rng.gen::<f64>() * total) {
+                return Some(node);
+            }
+
+            // Rounding left the running sums out of step with the rates
+            rates.rebuild();
         }
+    }
 
-        self.time += min_time;
+    /// Advances the simulation by one tau leap: every cell fires a number of
+    /// times drawn from a Poisson distribution with mean its rate times the
+    /// leap length, with all events using the states from the start of the
+    /// leap. The leap length is chosen so that on average `tolerance` of the
+    /// cells that can fire do so during the leap, which bounds how stale rates
+    /// become and how often events collide; smaller tolerances are more
+    /// accurate. Leaps are cut short at breakpoints of the payoff schedule.
+    /// When that would be too few events to be worth leaping over, a single
+    /// exact step is taken instead.
+    ///
+    /// Only birth–death updates can leap; under other rules this always takes
+    /// an exact step. Fails like [`BoneLattice::step`] if a cell has an
+    /// invalid rate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the kinetics are [`Kinetics::Scan`], which does not keep the
+    /// rates leaping needs.
+    pub fn leap<R: Rng + ?Sized>(&mut self, tolerance: f64, rng: &mut R) -> Result<Leap, RateError> {
+        self.settle_rate_error()?;
 
-        // Return info about what was changed
-        (min_time_idx, invasion_state)
+        let rates = self.rates.as_ref().expect("tau leaping needs the rates kept by direct kinetics");
+        let total = rates.total();
+
+        // Leaps end at the next breakpoint of the payoff schedule, so that
+        // each one uses a single matrix
+        let breakpoint = self.next_breakpoint();
+        let until_breakpoint = (breakpoint - self.time) * total;
+        let crosses = until_breakpoint < tolerance * rates.active() as f64;
+        let expected = if crosses { until_breakpoint } else { tolerance * rates.active() as f64 };
+
+        let leapable = self.update_rule == UpdateRule::BirthDeath;
+        if !leapable || expected < MIN_LEAP_EVENTS || total <= 0.0 {
+            let time = self.time;
+            let event = self.step(rng)?;
+            return Ok(Leap {
+                tau: self.time - time,
+                exact: true,
+                events: event.into_iter().collect(),
+            });
+        }
+
+        // Splitting a Poisson number of events among the cells in proportion
+        // to their rates is the same as drawing each cell's events
+        // separately, and only touches the cells that fire
+        let tau = if crosses { breakpoint - self.time } else { expected / total };
+        self.count_null_events(tau, rng);
+        let count = rng.sample(Poisson::new(expected).expect("mean is positive")) as usize;
+        let mut changes = Vec::with_capacity(count);
+        let mut events = Vec::with_capacity(count);
+        for _ in 0..count {
+            let Some(node) = self.sample_rates(rng) else { break };
+            let state = *self.state(node);
+            match self.choose_action(node, rng) {
+                Action::Invade(target) => {
+                    let target = match target {
+                        Neighbor::Node(target) => Some(target),
+                        _ => None,
+                    };
+                    events.push(Event::Invasion { source: node, target, state });
+                    if let Some(target) = target {
+                        changes.push((Some(node), target, state, *self.state(target)));
+                    }
+                },
+                Action::Transition(new_state) => {
+                    events.push(Event::Transition { node, state: new_state });
+                    changes.push((None, node, new_state, state));
+                },
+            }
+        }
+
+        // Observers sample the lattice as it was before the leap and see every
+        // change at its end
+        self.advance(tau);
+
+        // Later events for the same cell win, which picks one at random since
+        // the events are in random order
+        let mut observed = Vec::with_capacity(if self.observers.is_empty() { 0 } else { changes.len() });
+        for &(source, target, state, _) in &changes {
+            if !self.observers.is_empty() {
+                let old = *self.state(target);
+                observed.push(Change { time: self.time, source, target, old, new: state });
+            }
+            self.write_state(target, state);
+        }
+
+        // Fitness only needs regenerating for cells that ended the leap in a
+        // different state and the cells that have them as neighbors
+        let mut changed = Vec::with_capacity(changes.len() * (self.topology.degree(0) + 1));
+        for (_, target, _, old_state) in changes {
+            if *self.state(target) == old_state { continue }
+            changed.push(target);
+            self.topology.for_each_reverse_neighbor(target, &mut |other| changed.push(other));
+        }
+
+        // Cells near several invasions are only regenerated once
+        changed.sort_unstable();
+        changed.dedup();
+        let fitness: Vec<_> = changed.iter().map(|&node| self.compute_fitness(node)).collect();
+        for (&node, &fitness) in changed.iter().zip(&fitness) {
+            self.note_rate(node, fitness);
+        }
+        let event_rates: Vec<_> = changed.iter().zip(&fitness)
+            .map(|(&node, &fitness)| self.event_rate(node, fitness))
+            .collect();
+        if let Some(ref mut stored) = self.fitness {
+            for (&node, &fitness) in changed.iter().zip(&fitness) {
+                stored[node] = fitness;
+            }
+        }
+        self.rates.as_mut().expect("checked above").set_many(
+            changed.iter().copied().zip(event_rates));
+        let null_rates = self.null_rates.as_ref().map(|_| {
+            changed.iter().zip(&fitness)
+                .map(|(&node, &fitness)| self.null_rate(node, fitness))
+                .collect::<Vec<_>>()
+        });
+        if let (Some(tree), Some(null_rates)) = (&mut self.null_rates, null_rates) {
+            tree.set_many(changed.iter().copied().zip(null_rates));
+        }
+
+        self.notify(&observed);
+        if crosses {
+            self.cross_breakpoint(breakpoint);
+        }
+        Ok(Leap { tau, exact: false, events })
     }
 
-    /// Gets the number of cells in each state, with 0 being resorption, 1 being
-    /// formation, and 2 being quiescence.
+    /// Gets the number of cells in the domain in each state, with 0 being
+    /// resorption, 1 being formation, and 2 being quiescence.
     pub fn count(&self) -> (usize, usize, usize) {
-        let mut count: (usize, usize, usize) = (0, 0, 0);
-        for idx in LatticeIdx::cube_iter(self.size()) {
-            match self.state(idx) {
-                State::Resorption => count.0 += 1,
-                State::Formation => count.1 += 1,
-                State::Quiescence => count.2 += 1,
-            }
+        let [resorption, formation, quiescence] = self.counts;
+        (resorption, formation, quiescence)
+    }
+
+    /// Gets the number of cells in the domain in a state. Counts are kept up
+    /// to date as the simulation runs, so this takes O(1) time.
+    pub fn count_of(&self, state: State) -> usize {
+        self.counts[state as usize]
+    }
+
+    /// Number of cells in the domain.
+    pub fn domain_len(&self) -> usize {
+        self.domain_len
+    }
+}
+
+/// Reasons a [`BoneLattice`] cannot be constructed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitError {
+    /// The shape of the lattice is invalid.
+    Shape(ShapeError),
+    /// No offset of the neighborhood lies along an axis of the lattice.
+    EmptyNeighborhood,
+    /// The neighborhood reaches as far as the extent of an axis.
+    NeighborhoodTooLarge { axis: usize, reach: i16, extent: i16 },
+    /// The topology has no nodes.
+    NoNodes,
+    /// An edge refers to a node that does not exist.
+    NodeOutOfRange { node: usize, len: usize },
+}
+
+impl From<ShapeError> for InitError {
+    fn from(err: ShapeError) -> Self {
+        InitError::Shape(err)
+    }
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::Shape(err) => write!(f, "invalid shape: {}", err),
+            InitError::EmptyNeighborhood => {
+                write!(f, "the neighborhood has no offsets along the axes of the lattice")
+            },
+            InitError::NeighborhoodTooLarge { axis, reach, extent } => {
+                write!(f, "the neighborhood reaches {} cells along the {} axis, which must be less than its extent of {}",
+                    reach, ["x", "y", "z"][*axis], extent)
+            },
+            InitError::NoNodes => write!(f, "the topology has no nodes"),
+            InitError::NodeOutOfRange { node, len } => {
+                write!(f, "node {} does not exist in a topology of {} nodes", node, len)
+            },
         }
-        count
     }
 }
 
-/// The three populations that are competing
-#[derive(Debug, Clone, Copy)]
+impl std::error::Error for InitError {}
+
+/// A cell whose fitness does not give a valid rate, found at `time`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateError {
+    /// The fitness is negative and the [`RatePolicy`] rejects it.
+    Negative { node: usize, fitness: Real, time: f64 },
+    /// The fitness is infinite or NaN, which no policy can handle.
+    NonFinite { node: usize, fitness: Real, time: f64 },
+}
+
+impl RateError {
+    /// The cell with the invalid rate.
+    pub fn node(&self) -> usize {
+        match *self {
+            RateError::Negative { node, .. } | RateError::NonFinite { node, .. } => node,
+        }
+    }
+}
+
+impl fmt::Display for RateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateError::Negative { node, fitness, time } => {
+                write!(f, "node {} has negative fitness {} at t = {}", node, fitness, time)
+            },
+            RateError::NonFinite { node, fitness, time } => {
+                write!(f, "node {} has fitness {} at t = {}", node, fitness, time)
+            },
+        }
+    }
+}
+
+impl std::error::Error for RateError {}
+
+/// A reason a setting of a [`BoneLattice`] cannot be used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingError {
+    /// The minimum rate of [`RatePolicy::Clamp`] is negative, infinite or
+    /// NaN.
+    ClampMinimum { min: Real },
+    /// The temperature of [`UpdateRule::Fermi`] is not positive and finite.
+    Temperature { temperature: Real },
+}
+
+impl fmt::Display for SettingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingError::ClampMinimum { min } => {
+                write!(f, "a minimum rate of {} is not finite and non-negative", min)
+            },
+            SettingError::Temperature { temperature } => {
+                write!(f, "a temperature of {} is not positive and finite", temperature)
+            },
+        }
+    }
+}
+
+impl std::error::Error for SettingError {}
+
+/// The three populations that are competing. Each takes a single byte, and
+/// converts to the number used for it in dumps with `as u8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
 pub enum State {
-    Resorption,
-    Formation,
-    Quiescence
+    Resorption = 0,
+    Formation = 1,
+    Quiescence = 2,
+}
+
+impl State {
+    /// Every state, in the order of their numbers.
+    pub const ALL: [State; 3] = [State::Resorption, State::Formation, State::Quiescence];
+}
+
+impl fmt::Display for State {
+    /// Writes the name of the state, as accepted by [`State::from_str`].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            State::Resorption => "resorption",
+            State::Formation => "formation",
+            State::Quiescence => "quiescence",
+        })
+    }
+}
+
+impl FromStr for State {
+    type Err = ();
+
+    /// Parses either the number used in dumps or the name of the state.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" | "resorption" => Ok(State::Resorption),
+            "1" | "formation" => Ok(State::Formation),
+            "2" | "quiescence" => Ok(State::Quiescence),
+            _ => Err(()),
+        }
+    }
 }
 
 impl Distribution<State> for Standard {
@@ -151,3 +1406,33 @@ impl Distribution<State> for Standard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::topology::Graph;
+
+    #[test]
+    fn isolated_nodes_never_fire_without_rejections() {
+        // A ring on every node but 0, which has no neighbors
+        let len = 200;
+        let edges: Vec<_> = (1..len).map(|node| (node, node % (len - 1) + 1)).collect();
+        let graph = Graph::from_edges(len, &edges).unwrap();
+        let matrix = PayoffMatrix::new([1.0; 3], [2.0; 3], [3.0; 3]).unwrap();
+        let mut sim = BoneLattice::with_topology(Arc::new(graph), matrix, |node| State::ALL[node % 3]).unwrap();
+        sim.set_kinetics(Kinetics::RejectionFree);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            match sim.step(&mut rng).unwrap() {
+                Some(Event::Invasion { source, .. }) => assert_ne!(source, 0),
+                Some(Event::Transition { .. }) => panic!("no transitions were set"),
+                None => break,
+            }
+        }
+        assert!(sim.check_fitness().is_empty());
+    }
+}
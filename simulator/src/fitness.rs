@@ -0,0 +1,51 @@
+use crate::real::Real;
+
+/// How the payoffs a cell gets from playing each of its neighbors are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Payoffs {
+    /// Payoffs are summed, so cells with more neighbors get more payoff.
+    #[default]
+    Accumulated,
+    /// Payoffs are averaged over the neighbors played. Absorbing boundaries
+    /// and cells outside the domain are not played, and do not count.
+    Averaged,
+}
+
+/// How the combined payoff of a cell turns into its fitness. `w` is the
+/// intensity of selection: small values make fitness nearly the same for
+/// every cell, and large values let payoff differences dominate.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Selection {
+    /// Fitness is the payoff itself.
+    #[default]
+    Identity,
+    /// Fitness is `1 - w + w * payoff`.
+    Linear { w: Real },
+    /// Fitness is `exp(w * payoff)`, which is positive for any payoff.
+    Exponential { w: Real },
+}
+
+/// Turns the payoffs a cell gets from its neighbors into its fitness.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FitnessFunction {
+    pub payoffs: Payoffs,
+    pub selection: Selection,
+}
+
+impl FitnessFunction {
+    /// Gets the fitness of a cell whose payoffs from `played` neighbors sum to
+    /// `total`.
+    pub fn fitness(&self, total: Real, played: usize) -> Real {
+        let payoff = match self.payoffs {
+            Payoffs::Accumulated => total,
+            Payoffs::Averaged if played == 0 => 0.0,
+            Payoffs::Averaged => total / played as Real,
+        };
+
+        match self.selection {
+            Selection::Identity => payoff,
+            Selection::Linear { w } => 1.0 - w + w * payoff,
+            Selection::Exponential { w } => (w * payoff).exp(),
+        }
+    }
+}
@@ -1,23 +1,114 @@
-use std::ops;
+use std::{fmt, ops};
 
-/// 3D structure used to model the lattice
+use crate::layout::Layout;
+
+/// Structure used to model the lattice. Lattices always have three axes, but
+/// an axis with an extent of 1 is collapsed and takes no part in neighborhoods,
+/// so 1D and 2D lattices are those with two or one of these axes.
+///
+/// Indexing wraps every coordinate around its axis, so any index is valid; use
+/// [`Lattice::get`] to reject indices outside the lattice instead.
 #[derive(Debug, Clone)]
 pub struct Lattice<T> {
     data: Vec<T>,
-    pub size: i16,
+    shape: LatticeIdx,
+    layout: Layout,
 }
 
 impl<T> Lattice<T> {
-    pub fn filled<F: FnMut(LatticeIdx) -> T>(size: i16, mut filler: F) -> Self {
-        let data = Vec::with_capacity(size as usize * size as usize * size as usize);
-
-        let mut this = Self { data, size };
+    /// Creates a lattice of the provided shape, calling `filler` on each index
+    /// to get its initial value. Fails if any extent is not positive or if the
+    /// lattice would have too many cells to allocate.
+    pub fn filled<F: FnMut(LatticeIdx) -> T>(
+        shape: LatticeIdx,
+        filler: F
+    ) -> Result<Self, ShapeError> {
+        Self::filled_with_layout(shape, Layout::RowMajor, filler)
+    }
 
-        for idx in LatticeIdx::cube_iter(size) {
-            this[idx] = filler(idx)
+    /// Like [`Lattice::filled`], but stores the cells in the provided layout.
+    pub fn filled_with_layout<F: FnMut(LatticeIdx) -> T>(
+        shape: LatticeIdx,
+        layout: Layout,
+        mut filler: F
+    ) -> Result<Self, ShapeError> {
+        layout.check()?;
+        let volume = shape.checked_volume()?;
+        if volume > isize::MAX as usize / std::mem::size_of::<T>().max(1) {
+            return Err(ShapeError::TooManyCells);
         }
 
-        this
+        // Indices are visited in storage order
+        let data = (0..volume)
+            .map(|offset| filler(layout.idx(shape, offset)))
+            .collect::<Vec<_>>();
+
+        Ok(Self { data, shape, layout })
+    }
+
+    /// Order the cells are stored in.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Extent of the lattice along the x, y and z axes.
+    pub fn shape(&self) -> LatticeIdx {
+        self.shape
+    }
+
+    /// Number of axes with an extent greater than 1.
+    pub fn dimension(&self) -> usize {
+        self.shape.active_axes().count()
+    }
+
+    /// Whether an index lies inside the lattice without wrapping.
+    pub fn contains(&self, idx: LatticeIdx) -> bool {
+        idx.within(self.shape)
+    }
+
+    /// Gets the value at an index, or [`None`] if it lies outside the lattice.
+    pub fn get(&self, idx: LatticeIdx) -> Option<&T> {
+        if !self.contains(idx) { return None }
+        // SAFETY: the index was just checked to be inside the lattice
+        Some(unsafe { self.get_unchecked(idx) })
+    }
+
+    /// Mutable version of [`Lattice::get`].
+    pub fn get_mut(&mut self, idx: LatticeIdx) -> Option<&mut T> {
+        if !self.contains(idx) { return None }
+        // SAFETY: the index was just checked to be inside the lattice
+        Some(unsafe { self.get_unchecked_mut(idx) })
+    }
+
+    /// Gets the value at an index without wrapping or bounds checks.
+    ///
+    /// # Safety
+    ///
+    /// Every coordinate of `idx` must lie between 0 and the extent of its axis.
+    pub unsafe fn get_unchecked(&self, idx: LatticeIdx) -> &T {
+        self.data.get_unchecked(self.offset_unchecked(idx))
+    }
+
+    /// Mutable version of [`Lattice::get_unchecked`].
+    ///
+    /// # Safety
+    ///
+    /// Every coordinate of `idx` must lie between 0 and the extent of its axis.
+    pub unsafe fn get_unchecked_mut(&mut self, idx: LatticeIdx) -> &mut T {
+        let offset = self.offset_unchecked(idx);
+        self.data.get_unchecked_mut(offset)
+    }
+
+    fn offset_unchecked(&self, idx: LatticeIdx) -> usize {
+        self.layout.offset(self.shape, idx)
+    }
+
+    fn wrap(&self, idx: LatticeIdx) -> LatticeIdx {
+        LatticeIdx(
+            idx.0.rem_euclid(self.shape.0),
+            idx.1.rem_euclid(self.shape.1),
+            idx.2.rem_euclid(self.shape.2),
+        )
     }
 }
 
@@ -25,26 +116,87 @@ impl<T> ops::Index<LatticeIdx> for Lattice<T> {
     type Output = T;
 
     fn index(&self, idx: LatticeIdx) -> &Self::Output {
-        let first = (idx.0.rem_euclid(self.size as i16)) as usize * self.size as usize * self.size as usize;
-        let second = (idx.1.rem_euclid(self.size as i16)) as usize * self.size as usize;
-        let third = (idx.2.rem_euclid(self.size as i16)) as usize;
+        // SAFETY: wrapping puts the index inside the lattice
         unsafe {
-            self.data.get_unchecked(first + second + third)
+            self.get_unchecked(self.wrap(idx))
         }
     }
 }
 
 impl<T> ops::IndexMut<LatticeIdx> for Lattice<T> {
     fn index_mut(&mut self, idx: LatticeIdx) -> &mut Self::Output {
-        let first = (idx.0.rem_euclid(self.size as i16)) as usize * self.size as usize * self.size as usize;
-        let second = (idx.1.rem_euclid(self.size as i16)) as usize * self.size as usize;
-        let third = (idx.2.rem_euclid(self.size as i16)) as usize;
+        let idx = self.wrap(idx);
+        // SAFETY: wrapping puts the index inside the lattice
         unsafe {
-            self.data.get_unchecked_mut(first + second + third)
+            self.get_unchecked_mut(idx)
         }
     }
 }
 
+/// Reasons a lattice cannot be created with a particular shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeError {
+    /// The extent of an axis is zero or negative.
+    NonPositive { axis: usize, extent: i64 },
+    /// The extent of an axis does not fit in a coordinate.
+    TooLong { axis: usize, extent: i64 },
+    /// The total number of cells cannot be stored.
+    TooManyCells,
+    /// A view is longer along an axis than the lattice it looks into.
+    LargerThanLattice { axis: usize, extent: i64 },
+    /// Two lattices that must match have different extents along an axis.
+    Mismatch { axis: usize, expected: i64, found: i64 },
+    /// The simulation does not run on a lattice, so it has no shape.
+    NotALattice,
+    /// Tiles of a tiled layout must have a positive power of two as the edge.
+    InvalidTile { edge: i16 },
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const AXES: [&str; 3] = ["x", "y", "z"];
+        match self {
+            ShapeError::NonPositive { axis, extent } => {
+                write!(f, "extent of the {} axis must be positive, got {}", AXES[*axis], extent)
+            },
+            ShapeError::TooLong { axis, extent } => {
+                write!(f, "extent of the {} axis must be at most {}, got {}",
+                    AXES[*axis], i16::MAX, extent)
+            },
+            ShapeError::TooManyCells => write!(f, "lattice has too many cells"),
+            ShapeError::LargerThanLattice { axis, extent } => {
+                write!(f, "extent of the {} axis is larger than the lattice, got {}",
+                    AXES[*axis], extent)
+            },
+            ShapeError::Mismatch { axis, expected, found } => {
+                write!(f, "extent of the {} axis must be {}, got {}",
+                    AXES[*axis], expected, found)
+            },
+            ShapeError::NotALattice => write!(f, "the topology is not a lattice"),
+            ShapeError::InvalidTile { edge } => {
+                write!(f, "tile edge must be a positive power of two, got {}", edge)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ShapeError {}
+
+/// Checks that a shape has the extents of the one expected, failing with
+/// [`ShapeError::Mismatch`] on the first axis that differs.
+pub(crate) fn check_same_shape(expected: LatticeIdx, found: LatticeIdx) -> Result<(), ShapeError> {
+    for axis in 0..3 {
+        if expected.axis(axis) != found.axis(axis) {
+            return Err(ShapeError::Mismatch {
+                axis,
+                expected: expected.axis(axis) as i64,
+                found: found.axis(axis) as i64,
+            });
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LatticeIdx(pub i16, pub i16, pub i16);
 
@@ -54,37 +206,167 @@ impl LatticeIdx {
         LatticeIdx(num, num, num)
     }
 
-    pub fn cube_iter(size: i16) -> impl Iterator<Item = LatticeIdx> {
-        BoxIter {
-            exhausted: false,
-            pos: LatticeIdx(0, 0, 0),
-            high: LatticeIdx::cubed(size)
+    /// Validates the extent along each axis of a lattice and converts them to
+    /// a shape.
+    pub fn try_shape(extents: [i64; 3]) -> Result<LatticeIdx, ShapeError> {
+        let mut shape = LatticeIdx(0, 0, 0);
+        for (axis, extent) in extents.into_iter().enumerate() {
+            *shape.axis_mut(axis) = i16::try_from(extent)
+                .map_err(|_| ShapeError::TooLong { axis, extent })?;
+        }
+        shape.checked_volume()?;
+        Ok(shape)
+    }
+
+    /// Number of cells in a lattice with this index as its shape. Fails if an
+    /// extent is not positive or if the count overflows.
+    pub fn checked_volume(self) -> Result<usize, ShapeError> {
+        (0..3).try_fold(1usize, |volume, axis| {
+            let extent = self.axis(axis);
+            if extent <= 0 {
+                return Err(ShapeError::NonPositive { axis, extent: extent as i64 });
+            }
+            volume.checked_mul(extent as usize).ok_or(ShapeError::TooManyCells)
+        })
+    }
+
+    /// Moves this index by `offset` and wraps the result around a lattice of
+    /// the provided shape, without overflowing however far either reaches.
+    pub fn offset_within(self, offset: LatticeIdx, shape: LatticeIdx) -> LatticeIdx {
+        let mut idx = LatticeIdx(0, 0, 0);
+        for axis in 0..3 {
+            let coord = self.axis(axis) as i32 + offset.axis(axis) as i32;
+            *idx.axis_mut(axis) = coord.rem_euclid(shape.axis(axis) as i32) as i16;
         }
+        idx
     }
 
-    // pub fn box_iter(low: LatticeIdx, high: LatticeIdx) 
-    //     -> impl Iterator<Item = LatticeIdx>
-    // {
-    //     BoxIter {
-    //         exhausted: false,
-    //         pos: low,
-    //         high
-    //     }
-    // }
+    /// Whether every coordinate lies between 0 and the extent of its axis in
+    /// the provided shape.
+    pub fn within(self, shape: LatticeIdx) -> bool {
+        (0..3).all(|axis| (0..shape.axis(axis)).contains(&self.axis(axis)))
+    }
+
+    /// Iterates over the axes of this shape that are not collapsed, meaning
+    /// they have an extent greater than 1.
+    pub fn active_axes(self) -> impl Iterator<Item = usize> {
+        (0..3).filter(move |&axis| self.axis(axis) > 1)
+    }
+
+    /// Iterates over every index of a lattice with the provided shape.
+    pub fn shape_iter(shape: LatticeIdx) -> impl Iterator<Item = LatticeIdx> {
+        Self::box_iter(LatticeIdx(0, 0, 0), shape)
+    }
 
-    pub fn neighbor_iter(center: LatticeIdx)
+    /// Iterates over every index from `low` (inclusive) to `high` (exclusive)
+    /// along each axis, in the order cells are stored.
+    pub fn box_iter(low: LatticeIdx, high: LatticeIdx)
         -> impl Iterator<Item = LatticeIdx>
     {
-        NeighborIter {
-            center,
-            state: 0
+        BoxIter {
+            exhausted: low.0 >= high.0 || low.1 >= high.1 || low.2 >= high.2,
+            pos: low,
+            low,
+            high
+        }
+    }
+
+    /// Iterates over the sites offset from `center` in a lattice of the
+    /// provided shape, applying the boundary condition of each axis to any
+    /// neighbor that falls off the edge.
+    pub fn neighbor_iter<'a, S: Copy + 'a>(
+        center: LatticeIdx,
+        offsets: &'a [LatticeIdx],
+        shape: LatticeIdx,
+        boundaries: [Boundary<S>; 3],
+    ) -> impl Iterator<Item = Site<S>> + 'a {
+        offsets.iter()
+            .map(move |&offset| Self::resolve(center, offset, shape, &boundaries))
+    }
+
+    /// Determines what lies `offset` away from `center`. Coordinates inside
+    /// the lattice are always cells; coordinates outside it are handled by the
+    /// boundary of the first axis that is out of range. Coordinates are added
+    /// without overflowing, however far the offset reaches.
+    pub fn resolve<S: Copy>(
+        center: LatticeIdx,
+        offset: LatticeIdx,
+        shape: LatticeIdx,
+        boundaries: &[Boundary<S>; 3],
+    ) -> Site<S> {
+        let mut idx = center;
+        for (axis, boundary) in boundaries.iter().enumerate() {
+            let size = shape.axis(axis) as i32;
+            let coord = center.axis(axis) as i32 + offset.axis(axis) as i32;
+            *idx.axis_mut(axis) = match boundary {
+                _ if (0..size).contains(&coord) => coord as i16,
+                Boundary::Periodic => coord.rem_euclid(size) as i16,
+                Boundary::Reflecting => mirror(coord, size) as i16,
+                Boundary::Fixed(state) => return Site::Fixed(*state),
+                Boundary::Absorbing => return Site::Void,
+            };
         }
+        Site::Cell(idx)
     }
+
+    /// Gets the coordinate along an axis, with 0, 1 and 2 being x, y and z.
+    pub fn axis(self, axis: usize) -> i16 {
+        match axis {
+            0 => self.0,
+            1 => self.1,
+            2 => self.2,
+            _ => panic!("lattice has no axis {}", axis),
+        }
+    }
+
+    pub fn axis_mut(&mut self, axis: usize) -> &mut i16 {
+        match axis {
+            0 => &mut self.0,
+            1 => &mut self.1,
+            2 => &mut self.2,
+            _ => panic!("lattice has no axis {}", axis),
+        }
+    }
+}
+
+/// What happens to coordinates that fall off one edge of the lattice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary<S> {
+    /// The axis wraps around, so the lattice is a torus along it.
+    Periodic,
+    /// No-flux boundary: the lattice is mirrored at the edge, so a neighbor
+    /// past it is the cell as far inside, and nothing enters or leaves
+    /// through this edge. Face neighbors one cell past the edge are the cell
+    /// itself.
+    Reflecting,
+    /// The lattice is surrounded by a shell of cells permanently in a state.
+    Fixed(S),
+    /// Nothing lies past the edge, and anything sent there is lost.
+    Absorbing,
+}
+
+/// Mirrors a coordinate into `0..size` across the edges of an axis, so that
+/// -1 becomes 0, -2 becomes 1 and `size` becomes `size - 1`.
+fn mirror(coord: i32, size: i32) -> i32 {
+    let folded = coord.rem_euclid(2 * size);
+    if folded < size { folded } else { 2 * size - 1 - folded }
+}
+
+/// A neighbor of a cell after boundary conditions have been applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Site<S> {
+    /// A cell inside the lattice.
+    Cell(LatticeIdx),
+    /// A boundary cell that never changes state.
+    Fixed(S),
+    /// Empty space outside an absorbing boundary.
+    Void,
 }
 
 struct BoxIter {
     exhausted: bool,
     pos: LatticeIdx,
+    low: LatticeIdx,
     high: LatticeIdx,
 }
 
@@ -99,13 +381,12 @@ impl Iterator for BoxIter {
         self.pos.2 += 1;
         if self.pos.2 == self.high.2 {
             self.pos.1 += 1;
-            self.pos.2 = 0;
+            self.pos.2 = self.low.2;
             if self.pos.1 == self.high.1 {
                 self.pos.0 += 1;
-                self.pos.1 = 0;
+                self.pos.1 = self.low.1;
                 if self.pos.0 == self.high.0 {
                     self.exhausted = true;
-                    return None;
                 }
             }
         }
@@ -114,29 +395,6 @@ impl Iterator for BoxIter {
     }
 }
 
-struct NeighborIter {
-    center: LatticeIdx,
-    state: u8,
-}
-
-impl Iterator for NeighborIter {
-    type Item = LatticeIdx;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.state == 6 { return None }
-        self.state += 1;
-        Some(match self.state {
-            1 => self.center + LatticeIdx(-1, 0, 0),
-            2 => self.center + LatticeIdx( 1, 0, 0),
-            3 => self.center + LatticeIdx(0, -1, 0),
-            4 => self.center + LatticeIdx(0,  1, 0),
-            5 => self.center + LatticeIdx(0, 0, -1),
-            6 => self.center + LatticeIdx(0, 0,  1),
-            _ => unreachable!(),
-        })
-    }
-}
-
 impl ops::Add for LatticeIdx {
     type Output = Self;
 
@@ -0,0 +1,314 @@
+use crate::lattice::{LatticeIdx, ShapeError};
+
+/// The order in which the cells of a lattice are stored in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Cells are stored x-major, so neighbors along x are a whole yz plane
+    /// apart.
+    #[default]
+    RowMajor,
+    /// Cells are grouped into cubic tiles with `edge` cells per side, and the
+    /// tiles are stored one after another in row-major order. Cells inside a
+    /// tile are stored in Z (Morton) order, so cells close together on the
+    /// lattice are close together in memory along every axis. Tiles at the
+    /// far edges are cut short to fit the lattice.
+    Tiled { edge: i16 },
+}
+
+impl Layout {
+    /// Checks that the tile edge is a positive power of two.
+    pub fn check(self) -> Result<(), ShapeError> {
+        match self {
+            Layout::RowMajor => Ok(()),
+            Layout::Tiled { edge } if edge > 0 && edge.count_ones() == 1 => Ok(()),
+            Layout::Tiled { edge } => Err(ShapeError::InvalidTile { edge }),
+        }
+    }
+
+    /// Gets the position in storage of an index inside a lattice of the
+    /// provided shape.
+    pub fn offset(self, shape: LatticeIdx, idx: LatticeIdx) -> usize {
+        let edge = match self {
+            Layout::RowMajor => return row_major_offset(shape, idx),
+            Layout::Tiled { edge } => edge,
+        };
+        let bits = edge.trailing_zeros();
+        let mask = edge - 1;
+
+        // Every earlier x slab of tiles is full, as is every earlier y slab
+        // within this x slab and every earlier tile in this column
+        let tile = LatticeIdx(idx.0 >> bits, idx.1 >> bits, idx.2 >> bits);
+        let extents = tile_extents(shape, tile, edge);
+        let before = tile.0 as usize * edge as usize * shape.1 as usize * shape.2 as usize
+            + extents.0 as usize * tile.1 as usize * edge as usize * shape.2 as usize
+            + extents.0 as usize * extents.1 as usize * tile.2 as usize * edge as usize;
+
+        let local = LatticeIdx(idx.0 & mask, idx.1 & mask, idx.2 & mask);
+        before + tile_offset(extents, local)
+    }
+
+    /// Builds a table for each axis of the offset contributed by each
+    /// coordinate along it, such that the offset of an index is the sum of the
+    /// entries for its coordinates. This is possible for row-major layouts and
+    /// for tiled layouts where every extent is either a multiple of the tile
+    /// edge or smaller than it, since then every tile has the same extents.
+    pub fn axis_tables(self, shape: LatticeIdx) -> Option<[Vec<usize>; 3]> {
+        if !self.is_separable(shape) { return None }
+
+        Some([0, 1, 2].map(|axis| {
+            (0..shape.axis(axis))
+                .map(|coord| {
+                    let mut idx = LatticeIdx(0, 0, 0);
+                    *idx.axis_mut(axis) = coord;
+                    self.offset(shape, idx)
+                })
+                .collect()
+        }))
+    }
+
+    /// Builds a table that finds the index stored at a position faster than
+    /// [`Layout::idx`], for tiled layouts whose tiles all have the same
+    /// extents; see [`Layout::axis_tables`].
+    pub(crate) fn idx_table(self, shape: LatticeIdx) -> Option<IdxTable> {
+        let Layout::Tiled { edge } = self else { return None };
+        if !self.is_separable(shape) { return None }
+
+        let tile = LatticeIdx(edge.min(shape.0), edge.min(shape.1), edge.min(shape.2));
+        let volume = tile.0 as usize * tile.1 as usize * tile.2 as usize;
+        Some(IdxTable {
+            tile,
+            tiles_y: (shape.1 / tile.1) as usize,
+            tiles_z: (shape.2 / tile.2) as usize,
+            shift: volume.is_power_of_two().then(|| volume.trailing_zeros()),
+            local: (0..volume).map(|offset| tile_idx(tile, offset)).collect(),
+        })
+    }
+
+    /// Whether every tile has the same extents, which holds when every extent
+    /// is either a multiple of the tile edge or smaller than it.
+    fn is_separable(self, shape: LatticeIdx) -> bool {
+        match self {
+            Layout::RowMajor => true,
+            Layout::Tiled { edge } => (0..3).all(|axis| {
+                let extent = shape.axis(axis);
+                extent % edge == 0 || extent < edge
+            }),
+        }
+    }
+
+    /// Gets the index stored at a position, which is the inverse of
+    /// [`Layout::offset`].
+    pub fn idx(self, shape: LatticeIdx, offset: usize) -> LatticeIdx {
+        let edge = match self {
+            Layout::RowMajor => return row_major_idx(shape, offset),
+            Layout::Tiled { edge } => edge,
+        };
+        let edge_len = edge as usize;
+
+        // Peel off the full slabs of tiles before this one, one axis at a time
+        let x_slab = edge_len * shape.1 as usize * shape.2 as usize;
+        let tile_x = offset / x_slab;
+        let rest = offset % x_slab;
+        let extent_x = edge.min(shape.0 - tile_x as i16 * edge);
+
+        let y_slab = extent_x as usize * edge_len * shape.2 as usize;
+        let tile_y = rest / y_slab;
+        let rest = rest % y_slab;
+        let extent_y = edge.min(shape.1 - tile_y as i16 * edge);
+
+        let z_slab = extent_x as usize * extent_y as usize * edge_len;
+        let tile_z = rest / z_slab;
+        let rest = rest % z_slab;
+        let extent_z = edge.min(shape.2 - tile_z as i16 * edge);
+
+        let tile = LatticeIdx(tile_x as i16, tile_y as i16, tile_z as i16);
+        let local = tile_idx(LatticeIdx(extent_x, extent_y, extent_z), rest);
+        LatticeIdx(
+            tile.0 * edge + local.0,
+            tile.1 * edge + local.1,
+            tile.2 * edge + local.2,
+        )
+    }
+}
+
+/// Finds the index stored at a position of a tiled layout whose tiles all have
+/// the same extents, by looking up the position within its tile instead of
+/// decoding it bit by bit.
+#[derive(Debug, Clone)]
+pub(crate) struct IdxTable {
+    /// Extents of every tile.
+    tile: LatticeIdx,
+    /// Number of tiles along the y and z axes.
+    tiles_y: usize,
+    tiles_z: usize,
+    /// Shift that divides by the number of cells in a tile, if it is a power
+    /// of two.
+    shift: Option<u32>,
+    /// Index within a tile of each position in it.
+    local: Vec<LatticeIdx>,
+}
+
+impl IdxTable {
+    /// Gets the index stored at a position, like [`Layout::idx`].
+    pub fn idx(&self, offset: usize) -> LatticeIdx {
+        let (tile, local) = match self.shift {
+            Some(shift) => (offset >> shift, offset & (self.local.len() - 1)),
+            None => (offset / self.local.len(), offset % self.local.len()),
+        };
+
+        // Tiles are stored in row-major order
+        let tile_z = tile % self.tiles_z;
+        let rest = tile / self.tiles_z;
+        let tile_y = rest % self.tiles_y;
+        let tile_x = rest / self.tiles_y;
+
+        let local = self.local[local];
+        LatticeIdx(
+            tile_x as i16 * self.tile.0 + local.0,
+            tile_y as i16 * self.tile.1 + local.1,
+            tile_z as i16 * self.tile.2 + local.2,
+        )
+    }
+}
+
+fn row_major_offset(shape: LatticeIdx, idx: LatticeIdx) -> usize {
+    let first = idx.0 as usize * shape.1 as usize * shape.2 as usize;
+    let second = idx.1 as usize * shape.2 as usize;
+    let third = idx.2 as usize;
+    first + second + third
+}
+
+fn row_major_idx(shape: LatticeIdx, offset: usize) -> LatticeIdx {
+    let third = offset % shape.2 as usize;
+    let rest = offset / shape.2 as usize;
+    let second = rest % shape.1 as usize;
+    let first = rest / shape.1 as usize;
+    LatticeIdx(first as i16, second as i16, third as i16)
+}
+
+/// Extent of a tile along each axis once it is cut to fit the lattice.
+fn tile_extents(shape: LatticeIdx, tile: LatticeIdx, edge: i16) -> LatticeIdx {
+    LatticeIdx(
+        edge.min(shape.0 - tile.0 * edge),
+        edge.min(shape.1 - tile.1 * edge),
+        edge.min(shape.2 - tile.2 * edge),
+    )
+}
+
+/// Position of a cell within a tile. Tiles whose extents are all powers of two
+/// interleave the bits of the coordinates, with z taking the lowest bit of
+/// each round; axes run out of bits early in tiles that are not cubes. Any
+/// other tile is stored in row-major order.
+fn tile_offset(extents: LatticeIdx, local: LatticeIdx) -> usize {
+    if !is_morton(extents) {
+        return row_major_offset(extents, local);
+    }
+
+    let bits = [extents.0, extents.1, extents.2].map(|extent| extent.trailing_zeros());
+    let coords = [local.0, local.1, local.2];
+    let mut offset = 0;
+    let mut pos = 0;
+    for bit in 0..bits.iter().copied().max().unwrap_or(0) {
+        for axis in (0..3).rev() {
+            if bit < bits[axis] {
+                offset |= ((coords[axis] as usize >> bit) & 1) << pos;
+                pos += 1;
+            }
+        }
+    }
+    offset
+}
+
+/// Inverse of [`tile_offset`].
+fn tile_idx(extents: LatticeIdx, offset: usize) -> LatticeIdx {
+    if !is_morton(extents) {
+        return row_major_idx(extents, offset);
+    }
+
+    let bits = [extents.0, extents.1, extents.2].map(|extent| extent.trailing_zeros());
+    let mut coords = [0i16; 3];
+    let mut pos = 0;
+    for bit in 0..bits.iter().copied().max().unwrap_or(0) {
+        for axis in (0..3).rev() {
+            if bit < bits[axis] {
+                coords[axis] |= (((offset >> pos) & 1) as i16) << bit;
+                pos += 1;
+            }
+        }
+    }
+    LatticeIdx(coords[0], coords[1], coords[2])
+}
+
+fn is_morton(extents: LatticeIdx) -> bool {
+    extents.0.count_ones() == 1 && extents.1.count_ones() == 1 && extents.2.count_ones() == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layouts() -> Vec<Layout> {
+        let mut layouts = vec![Layout::RowMajor];
+        layouts.extend([1, 2, 4, 8, 16].map(|edge| Layout::Tiled { edge }));
+        layouts
+    }
+
+    fn shapes() -> Vec<LatticeIdx> {
+        let extents = [1, 2, 3, 4, 5, 8, 12, 16, 17, 32];
+        let mut shapes = Vec::new();
+        for &x in &extents {
+            for &y in &extents {
+                for &z in &[1, 3, 8, 17] {
+                    shapes.push(LatticeIdx(x, y, z));
+                }
+            }
+        }
+        shapes
+    }
+
+    fn indices(shape: LatticeIdx) -> impl Iterator<Item = LatticeIdx> {
+        (0..shape.0).flat_map(move |x| {
+            (0..shape.1).flat_map(move |y| (0..shape.2).map(move |z| LatticeIdx(x, y, z)))
+        })
+    }
+
+    #[test]
+    fn offsets_are_a_permutation() {
+        for layout in layouts() {
+            for shape in shapes() {
+                let len = shape.0 as usize * shape.1 as usize * shape.2 as usize;
+                let mut seen = vec![false; len];
+                for idx in indices(shape) {
+                    let offset = layout.offset(shape, idx);
+                    assert!(offset < len, "{:?} puts {:?} of {:?} past the end", layout, idx, shape);
+                    assert!(!seen[offset], "{:?} puts two cells of {:?} at {}", layout, shape, offset);
+                    seen[offset] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn idx_inverts_offset() {
+        for layout in layouts() {
+            for shape in shapes() {
+                for idx in indices(shape) {
+                    assert_eq!(layout.idx(shape, layout.offset(shape, idx)), idx, "{:?} on {:?}", layout, shape);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn idx_table_matches_idx() {
+        for layout in layouts() {
+            for shape in shapes() {
+                let Some(table) = layout.idx_table(shape) else { continue };
+                let len = shape.0 as usize * shape.1 as usize * shape.2 as usize;
+                for offset in 0..len {
+                    assert_eq!(table.idx(offset), layout.idx(shape, offset), "{:?} on {:?}", layout, shape);
+                }
+            }
+        }
+    }
+}
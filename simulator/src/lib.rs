@@ -0,0 +1,19 @@
+//! Spatial evolutionary game simulation of the bone remodeling populations.
+
+pub mod lattice;
+pub mod layout;
+pub mod view;
+pub mod neighborhood;
+pub mod topology;
+pub mod payoff_matrix;
+pub mod fitness;
+pub mod bone_lattice;
+pub mod real;
+pub mod regions;
+pub mod observer;
+pub mod rng;
+pub mod schedule;
+pub mod stopping;
+pub mod transitions;
+
+mod rate_tree;
@@ -1,19 +1,40 @@
-mod lattice;
-mod payoff_matrix;
-mod bone_lattice;
-
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
-use std::time::Instant;
-use std::io::{Write, BufWriter};
+use std::time::{Duration, Instant};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::str::FromStr;
+
+use image::{ImageBuffer, Rgb};
+use spatial_sim::lattice::{Boundary, Lattice, LatticeIdx};
+use spatial_sim::layout::Layout;
+use spatial_sim::payoff_matrix::PayoffMatrix;
+use spatial_sim::fitness::{FitnessFunction, Payoffs, Selection};
+use spatial_sim::bone_lattice::{BoneLattice, Event, Kinetics, RatePolicy, State, UpdateRule};
+use spatial_sim::neighborhood::Neighborhood;
+use spatial_sim::real::Real;
+use spatial_sim::regions::PayoffRegions;
+use spatial_sim::rng::SimRng;
+use spatial_sim::schedule::{Interpolation, PayoffSchedule};
+use spatial_sim::stopping::{StopCondition, Stopper};
+use spatial_sim::topology::{Graph, Grid, Topology};
+use spatial_sim::transitions::TransitionRates;
 
-use image::{ImageBuffer, Rgb, ImageOutputFormat};
-use lattice::LatticeIdx;
-use payoff_matrix::PayoffMatrix;
-use bone_lattice::{BoneLattice, State};
+use rand::{Rng, SeedableRng};
 
-use rand::Rng;
+/// A simulation created by "init", along with what is needed to record and
+/// reproduce it.
+struct Run {
+    lattice: BoneLattice,
+    /// Every event so far and the time it happened, written by "dump steps".
+    step_buf: Vec<(f64, Event)>,
+    rng: SimRng,
+    /// Seed `rng` was created from, recorded in every dump.
+    seed: u64,
+    /// Number of steps or leaps between checks of the stored fitness values,
+    /// set by "check".
+    check_every: Option<u64>,
+}
 
 fn main() {
 
@@ -26,7 +47,7 @@ fn main() {
         ctrlc_clone
     };
 
-    let mut lattice: Option<(BoneLattice, Vec<(LatticeIdx, State)>)> = None;
+    let mut lattice: Option<Run> = None;
 
     println!("Will's research project: MATH 89S (Spring 2023)");
     println!("Type \"help\" for a list of commands");
@@ -59,37 +80,150 @@ fn main() {
 
 fn run_command(
     mut command: UserCommand,
-    lattice: &mut Option<(BoneLattice, Vec<(LatticeIdx, State)>)>,
+    lattice: &mut Option<Run>,
     ctrlc: Arc<AtomicBool>
 ) -> Option<()> {
     match command.identifier {
         "init" => {
-            let size = command.get_int_arg("size")?;
-            let matrix = PayoffMatrix::by_params(
-                [
-                    command.get_float_arg("alpha1")?,
-                    command.get_float_arg("alpha2")?,
-                    command.get_float_arg("alpha3")?,
-                ],
-                [
-                    command.get_float_arg("beta1")?,
-                    command.get_float_arg("beta2")?,
-                    command.get_float_arg("beta3")?,
-                ]
-            );
+            let shape_arg = command.get_string_arg("shape")?;
+            let alpha = [
+                command.get_float_arg("alpha1")?,
+                command.get_float_arg("alpha2")?,
+                command.get_float_arg("alpha3")?,
+            ];
+            let beta = [
+                command.get_float_arg("beta1")?,
+                command.get_float_arg("beta2")?,
+                command.get_float_arg("beta3")?,
+            ];
+            let omega = match command.get_option("omega") {
+                Some(arg) => match arg.parse() {
+                    Ok(omega) => omega,
+                    Err(_) => {
+                        println!("Expected a float for omega, got {}", arg);
+                        return None;
+                    }
+                },
+                None => 0.1,
+            };
+            let matrix = match PayoffMatrix::by_params(alpha, beta, omega) {
+                Ok(matrix) => matrix,
+                Err(err) => {
+                    println!("Invalid payoff matrix: {}", err);
+                    return None;
+                }
+            };
+            let fitness_function = FitnessFunction {
+                payoffs: match command.get_option("payoffs") {
+                    Some("accumulated") | None => Payoffs::Accumulated,
+                    Some("averaged") => Payoffs::Averaged,
+                    Some(arg) => {
+                        println!("Unknown payoffs: {}", arg);
+                        return None;
+                    }
+                },
+                selection: match command.get_option("selection") {
+                    Some(arg) => parse_selection(arg)?,
+                    None => Selection::Identity,
+                },
+            };
+
+            // Lattices are made from their shape and graphs from an edge list
+            let topology: Arc<dyn Topology> = match shape_arg.strip_prefix("graph:") {
+                Some(path) => Arc::new(load_graph(path)?),
+                None => {
+                    let shape = parse_shape(&shape_arg)?;
+                    let boundaries = match command.get_option("boundary") {
+                        Some(arg) => parse_boundaries(arg)?,
+                        None => [Boundary::Periodic; 3],
+                    };
+                    let neighborhood = match command.get_option("neighborhood") {
+                        Some(arg) => parse_neighborhood(arg)?,
+                        None => Neighborhood::default(),
+                    };
+                    let layout = match command.get_option("layout") {
+                        Some(arg) => parse_layout(arg)?,
+                        None => Layout::RowMajor,
+                    };
+                    let grid = Grid::new(shape, boundaries, neighborhood)
+                        .and_then(|grid| Ok(grid.with_layout(layout)?));
+                    match grid {
+                        Ok(grid) => Arc::new(grid),
+                        Err(err) => {
+                            println!("Error creating lattice: {}", err);
+                            return None;
+                        }
+                    }
+                }
+            };
+            let fitness_stored = match command.get_option("fitness") {
+                Some("stored") | None => true,
+                Some("computed") => false,
+                Some(arg) => {
+                    println!("Unknown fitness storage: {}", arg);
+                    return None;
+                }
+            };
+            let kinetics = match command.get_option("kinetics") {
+                Some(arg) => parse_kinetics(arg)?,
+                None => Kinetics::default(),
+            };
+            let update_rule = match command.get_option("update") {
+                Some(arg) => parse_update_rule(arg)?,
+                None => UpdateRule::default(),
+            };
+            let rate_policy = match command.get_option("rates") {
+                Some(arg) => parse_rate_policy(arg)?,
+                None => RatePolicy::default(),
+            };
+            // Runs without a seed get a random one, so that they can still be
+            // reproduced from the seed recorded in their dumps
+            let seed = match command.get_option("seed") {
+                Some(arg) => match arg.parse() {
+                    Ok(seed) => seed,
+                    Err(_) => {
+                        println!("Expected a seed, got {}", arg);
+                        return None;
+                    }
+                },
+                None => rand::thread_rng().gen(),
+            };
             command.error_on_args()?;
 
             // Create the lattice
-            let mut rng = rand::thread_rng();
+            let mut rng = SimRng::seed_from_u64(seed);
 
-            *lattice = Some(
-                (
-                    BoneLattice::new(size as i16, matrix, |_| {
-                        rng.gen::<State>()
-                    }),
-                    Vec::new()
-                )
-            );
+            let new_lattice = BoneLattice::random(topology, matrix, &mut rng);
+            match new_lattice {
+                Ok(mut new_lattice) => {
+                    new_lattice.set_fitness_stored(fitness_stored);
+                    new_lattice.set_kinetics(kinetics);
+                    if let Err(err) = new_lattice.set_update_rule(update_rule) {
+                        println!("Error creating lattice: {}", err);
+                        return None;
+                    }
+                    new_lattice.set_fitness_function(fitness_function);
+                    if let Err(err) = new_lattice.set_rate_policy(rate_policy) {
+                        println!("Error creating lattice: {}", err);
+                        return None;
+                    }
+                    if let Some(err) = new_lattice.rate_error() {
+                        println!("Error creating lattice: {}", err);
+                        return None;
+                    }
+                    *lattice = Some(Run {
+                        lattice: new_lattice,
+                        step_buf: Vec::new(),
+                        rng,
+                        seed,
+                        check_every: None,
+                    })
+                },
+                Err(err) => {
+                    println!("Error creating lattice: {}", err);
+                    return None;
+                }
+            }
         },
         "step" => {
 
@@ -97,7 +231,7 @@ fn run_command(
             command.error_on_args()?;
 
             // Ensure there's a lattice
-            let (lattice, step_buf) = match lattice {
+            let Run { lattice, step_buf, rng, check_every, .. } = match lattice {
                 Some(ref mut stuff) => stuff,
                 None => {
                     println!("Use \"init\" or \"load\" to create a lattice");
@@ -107,8 +241,9 @@ fn run_command(
 
             let real_pre_time = Instant::now();
             let sim_pre_time = lattice.time;
+            let null_pre = lattice.null_events();
 
-            step_buf.push(lattice.step());
+            record_step(lattice, rng, step_buf)?;
             println!("First step completed in {}ms", real_pre_time.elapsed().as_millis());
 
             for i in 1..count {
@@ -119,7 +254,10 @@ fn run_command(
                     return None;
                 }
 
-                step_buf.push(lattice.step());
+                record_step(lattice, rng, step_buf)?;
+                if check_every.is_some_and(|every| (i as u64 + 1).is_multiple_of(every)) {
+                    report_divergences(lattice);
+                }
             }
 
             let sim_post_time = lattice.time;
@@ -129,9 +267,12 @@ fn run_command(
                 sim_post_time, 
                 real_pre_time.elapsed().as_millis()
             );
+            if lattice.kinetics() == Kinetics::RejectionFree {
+                println!("Skipped {} invasions that changed nothing", lattice.null_events() - null_pre);
+            }
         }
         "time" => {
-            let (lattice, _step_buf) = match lattice {
+            let Run { lattice, .. } = match lattice {
                 Some(ref mut stuff) => stuff,
                 None => {
                     println!("Use \"init\" or \"load\" to create a lattice");
@@ -141,13 +282,25 @@ fn run_command(
 
             println!("Simulation time is t = {}", lattice.time);
         }
+        "seed" => {
+            command.error_on_args()?;
+            let Run { seed, .. } = match lattice {
+                Some(ref mut stuff) => stuff,
+                None => {
+                    println!("Use \"init\" or \"load\" to create a lattice");
+                    return None;
+                },
+            };
+
+            println!("Seed is {}", seed);
+        }
         "sim" => {
 
-            let time_step = command.get_float_arg("time_step")?;
+            let time_step: f64 = command.get_float_arg("time_step")?;
             command.error_on_args()?;
             
             // Ensure there's a lattice
-            let (lattice, step_buf) = match lattice {
+            let Run { lattice, step_buf, rng, check_every, .. } = match lattice {
                 Some(ref mut stuff) => stuff,
                 None => {
                     println!("Use \"init\" or \"load\" to create a lattice");
@@ -159,9 +312,10 @@ fn run_command(
             let init_time = lattice.time;
             let final_time = init_time + time_step;
             let mut steps: u32 = 1;
+            let null_pre = lattice.null_events();
 
             // Perform one step to get time of first step
-            step_buf.push(lattice.step());
+            record_step(lattice, rng, step_buf)?;
             let first_step_time = real_start.elapsed();
             println!("First step completed in {}ms", first_step_time.as_millis());
 
@@ -175,8 +329,11 @@ fn run_command(
                     return None;
                 }
 
-                step_buf.push(lattice.step());
+                record_step(lattice, rng, step_buf)?;
                 steps += 1;
+                if check_every.is_some_and(|every| (steps as u64).is_multiple_of(every)) {
+                    report_divergences(lattice);
+                }
 
                 if last_log.elapsed().as_secs() >= 10 {
 
@@ -189,10 +346,10 @@ fn run_command(
                     
                     // Estimate the time remaining
                     let sim_time_left = time_step - (lattice.time - init_time);
-                    let estimate = elapsed.as_secs_f32() * sim_time_left / progress;
+                    let estimate = elapsed.as_secs_f64() * sim_time_left / progress;
 
                     println!("{:.2}s elapsed: {} steps completed ({:.2}% progress; est. {:.2}s remaining)", 
-                        elapsed.as_secs_f32(),
+                        elapsed.as_secs_f64(),
                         steps,
                         progress_percent * 100.0,
                         estimate
@@ -201,6 +358,72 @@ fn run_command(
             }
 
             println!("Done; {} steps completed", steps);
+            if lattice.kinetics() == Kinetics::RejectionFree {
+                println!("Skipped {} invasions that changed nothing", lattice.null_events() - null_pre);
+            }
+        }
+        "run" => {
+            match &*command.get_string_arg("until")? {
+                "until" => {},
+                other => {
+                    println!("Expected \"until\", got {}", other);
+                    return None;
+                }
+            }
+            let conditions = command.take_rest()
+                .into_iter()
+                .map(parse_stop_condition)
+                .collect::<Option<Vec<_>>>()?;
+            command.error_on_args()?;
+            if conditions.is_empty() {
+                println!("Expected at least one stopping condition");
+                return None;
+            }
+
+            // Ensure there's a lattice
+            let Run { lattice, step_buf, rng, check_every, .. } = match lattice {
+                Some(ref mut stuff) => stuff,
+                None => {
+                    println!("Use \"init\" or \"load\" to create a lattice");
+                    return None;
+                },
+            };
+
+            let real_start = Instant::now();
+            let mut stopper = Stopper::new(conditions);
+            let mut steps: u64 = 0;
+            let mut last_log = Instant::now();
+            let reason = loop {
+                if let Some(condition) = stopper.check(lattice) {
+                    break condition.to_string();
+                }
+                if lattice.time.is_infinite() {
+                    break "nothing can change any more".to_owned();
+                }
+
+                if ctrlc.load(std::sync::atomic::Ordering::Relaxed) {
+                    println!("Aborted; {} steps completed and t = {}", steps, lattice.time);
+                    ctrlc.store(false, std::sync::atomic::Ordering::Relaxed);
+                    return None;
+                }
+
+                record_step(lattice, rng, step_buf)?;
+                steps += 1;
+                if check_every.is_some_and(|every| steps.is_multiple_of(every)) {
+                    report_divergences(lattice);
+                }
+
+                if last_log.elapsed().as_secs() >= 10 {
+                    last_log = Instant::now();
+                    println!("{:.2}s elapsed: {} steps completed and t = {}",
+                        real_start.elapsed().as_secs_f64(), steps, lattice.time);
+                }
+            };
+
+            println!("Stopped because {} at t = {} after {} steps in {}ms",
+                reason, lattice.time, steps, real_start.elapsed().as_millis());
+            let count = lattice.count();
+            println!("resorption: {}, formation: {}, quiescence: {}", count.0, count.1, count.2);
         }
         // "load" => {
         //     match &*command.get_string_arg("kind")? {
@@ -240,7 +463,7 @@ fn run_command(
         "count" => {
             command.error_on_args();
             // Ensure there's a lattice
-            let (lattice, _step_buf) = match lattice {
+            let Run { lattice, .. } = match lattice {
                 Some(ref mut stuff) => stuff,
                 None => {
                     println!("Use \"init\" or \"load\" to create a lattice");
@@ -255,7 +478,7 @@ fn run_command(
         }
         "dump" => {
             // Ensure there's a lattice
-            let (lattice, step_buf) = match lattice {
+            let Run { lattice, step_buf, seed, .. } = match lattice {
                 Some(ref mut stuff) => stuff,
                 None => {
                     println!("Use \"init\" or \"load\" to create a lattice");
@@ -265,43 +488,18 @@ fn run_command(
 
             let kind = command.get_string_arg("type")?;
             let file = command.get_string_arg("path")?;
-
-            let mut open_options = std::fs::OpenOptions::new();
-            open_options
-                .write(true)
-                .create_new(true);
+            let region = match command.get_option("region") {
+                Some(arg) => Some(parse_region(arg)?),
+                None => None,
+            };
+            command.error_on_args()?;
 
             match &*kind {
-                "csv" => {
-                    let file_result = open_options.open(&file);
-                    let mut file = match file_result {
-                        Ok(x) => x,
-                        Err(err) => {
-                            println!("Error opening file: {}", err);
-                            return None;
-                        },
-                    };
-                    for i in 0..lattice.size() {
-                        for j in 0..lattice.size() {
-                            for k in 0..lattice.size() {
-                                let number = match lattice.state(LatticeIdx(i, j, k)) {
-                                    State::Resorption => 0,
-                                    State::Formation => 1,
-                                    State::Quiescence => 2,
-                                };
-                                write!(file, "{}", number).unwrap();
-                                write!(file, ",").unwrap();
-                            }
-                            write!(file, "\n").unwrap();
-                        }
-                        write!(file, "\n").unwrap();
-                    }
-                }
-                "count" => {
-                    let file_result = OpenOptions::new()
-                        .append(true)
-                        .create(true)
-                        .open(file);
+                "csv" => dump_csv(lattice, &file, region, *seed)?,
+                "count" => dump_count(lattice, &file, region, *seed)?,
+                "img" => dump_img(lattice, &file, region, *seed)?,
+                "steps" => {
+                    let file_result = new_file_options().open(file);
                     let mut file = match file_result {
                         Ok(x) => x,
                         Err(err) => {
@@ -309,165 +507,1577 @@ fn run_command(
                             return None;
                         },
                     };
-                    let count = lattice.count();
-                    writeln!(file, "{:.5},{},{},{}", lattice.time, count.0, count.1, count.2).unwrap();
-                }
-                "img" => {
-                    let path = std::path::PathBuf::from(file);
-                    for img_idx in 0..lattice.size() {
-                        let mut img = ImageBuffer::<Rgb<u8>, _>::new(
-                            lattice.size() as u32, lattice.size() as u32);
-                        for (i, j, pixel) in img.enumerate_pixels_mut() {
-                            *pixel = match lattice.state(LatticeIdx(img_idx, i as i16, j as i16)) {
-                                State::Resorption => Rgb([0, 0, 255]),
-                                State::Formation => Rgb([150, 200, 150]),
-                                State::Quiescence => Rgb([255, 255, 0]),
-                            }
-                        }
-                        let writer_result = open_options
-                            .open(path.join(&format!("layer{}.png", img_idx)));
-                        let writer = match writer_result {
-                            Ok(x) => x,
-                            Err(err) => {
-                                println!("Error opening file: {}", err);
-                                return None;
-                            }
+                    // Cells of lattices are written by index, and nodes of
+                    // other topologies by id. Invasions end with the cell
+                    // that took the state, left empty if the invasion
+                    // reached none, and transitions leave those fields empty
+                    writeln!(file, "# seed={}", seed).unwrap();
+                    for &(time, event) in step_buf.iter() {
+                        let (node, target, state, kind) = match event {
+                            Event::Invasion { source, target, state } => (source, target, state, "invasion"),
+                            Event::Transition { node, state } => (node, None, state, "transition"),
                         };
-                        let mut writer = BufWriter::new(writer);
-                        let write_result = img.write_to(&mut writer, ImageOutputFormat::Png);
-                        match write_result {
-                            Ok(_) => {},
-                            Err(err) => {
-                                println!("Error writing image: {}", err);
-                                return None;
+                        match lattice.grid() {
+                            Some(grid) => {
+                                let idx = grid.idx(node);
+                                let target = match target {
+                                    Some(target) => {
+                                        let idx = grid.idx(target);
+                                        format!("{},{},{}", idx.0, idx.1, idx.2)
+                                    },
+                                    None => ",,".to_string(),
+                                };
+                                writeln!(file, "{},{},{},{},{},{},{}", time, idx.0, idx.1, idx.2, state_number(state), kind, target).unwrap();
+                            },
+                            None => {
+                                let target = target.map(|target| target.to_string()).unwrap_or_default();
+                                writeln!(file, "{},{},{},{},{}", time, node, state_number(state), kind, target).unwrap();
                             },
                         }
                     }
-                },
-                "steps" => {
-                    let file_result = open_options.open(file);
-                    let mut file = match file_result {
-                        Ok(x) => x,
-                        Err(err) => {
-                            println!("Error opening file: {}", err);
-                            return None;
-                        },
-                    };
-                    for (idx, state) in step_buf {
-                        let state_num = match state {
-                            State::Resorption => 0,
-                            State::Formation => 1,
-                            State::Quiescence => 2,
-                        };
-                        writeln!(file, "{},{},{},{}", idx.0, idx.1, idx.2, state_num).unwrap();
-                    }
                 }
                 _ => {
                     println!("Unknown file type");
                 }
             }
         }
-        "help" => {
-            println!("List of all commands:");
-            println!("\texit");
-            println!("\t\tExits the simulator. THIS DISCARDS ANY UNSAVED DATA!!");
-            println!("\tinit <size: int> <alpha1: float> <alpha2: float> <alpha3: float> <beta1: float> <beta2: float> <beta3: float>");
-            println!("\t\tInitializes the lattice in a random state and sets up the payoff matrix");
-            println!("\tstep <steps: int>");
-            println!("\t\tPerforms the specified number of simulation steps");
-            println!("\tsim <time: float>");
-            println!("\t\tRuns the simulation for the provided amount of simulation time");
-            println!("\ttime");
-            println!("\t\tPrints the current simulation time");
-            println!("\tcount");
-            println!("\t\tPrints the number of cells in each state");
-            println!("\tdump csv <file: str>");
-            println!("\t\tCreates a new CSV file and saves the current lattice state to it");
-            println!("\tdump img <folder: str>");
-            println!("\t\tPopulates the specified folder with an image representation fo the current lattice state");
-            println!("\tdump count <file: str>");
-            println!("\t\tDumps the number of cells in each state to the provided file");
-            println!("\tdump steps <file: str>");
-            println!("\t\tPrints all the simulation steps made to the specified file");
-        }
-        _ => {
-            println!("That command doesn't exist (type \"help\")");
-        }
-    };
+        "mask" => {
+            // Ensure there's a lattice
+            let Run { lattice, .. } = match lattice {
+                Some(ref mut stuff) => stuff,
+                None => {
+                    println!("Use \"init\" or \"load\" to create a lattice");
+                    return None;
+                },
+            };
 
-    Some(())
-}
+            let mask = match &*command.get_string_arg("type")? {
+                "csv" => Some(load_csv_mask(&command.get_string_arg("path")?)?),
+                "img" => Some(load_img_mask(&command.get_string_arg("path")?)?),
+                "clear" => None,
+                _ => {
+                    println!("Unknown mask type");
+                    return None;
+                }
+            };
+            command.error_on_args()?;
 
-/// A processed command issued by the user
-struct UserCommand<'a> {
-    pub identifier: &'a str,
-    arg_iter: std::str::Split<'a, &'static str>,
-}
+            if let Err(err) = lattice.set_mask(mask) {
+                println!("Mask does not fit the lattice: {}", err);
+                return None;
+            }
+        }
+        "check" => {
+            let every = match command.get_option("every") {
+                Some("off") => Some(None),
+                Some(arg) => match arg.parse::<u64>() {
+                    Ok(every) if every > 0 => Some(Some(every)),
+                    _ => {
+                        println!("Expected a positive number of steps or \"off\", got {}", arg);
+                        return None;
+                    },
+                },
+                None => None,
+            };
+            command.error_on_args()?;
 
-impl<'a> UserCommand<'a, > {
-    pub fn new(command: &'a str) -> Option<Self> {
-        let mut args = command.split(" ");
-        args.next().map(|identifier| {
-            UserCommand {
-                identifier,
-                arg_iter: args
+            // Ensure there's a lattice
+            let Run { lattice, check_every, .. } = match lattice {
+                Some(ref mut stuff) => stuff,
+                None => {
+                    println!("Use \"init\" or \"load\" to create a lattice");
+                    return None;
+                },
+            };
+
+            if let Some(every) = every {
+                *check_every = every;
             }
-        })
-    }
- 
-    /// Gets a string arg and prints an error message otherwise.
-    pub fn get_string_arg(&mut self, name: &str) -> Option<String> {
-        match self.arg_iter.next() {
-            Some(str) => Some(str.to_owned()),
-            None => {
-                println!("Expected string argument: {}", name);
-                None
+            if report_divergences(lattice) == 0 {
+                println!("Stored fitness and rates of all {} cells match", lattice.len());
             }
         }
-    }
+        "transition" => {
+            // Ensure there's a lattice
+            let Run { lattice, .. } = match lattice {
+                Some(ref mut stuff) => stuff,
+                None => {
+                    println!("Use \"init\" or \"load\" to create a lattice");
+                    return None;
+                },
+            };
 
-    /// Gets a string arg and prints an error message otherwise.
-    pub fn get_int_arg(&mut self, name: &str) -> Option<i64> {
-        let arg = match self.arg_iter.next() {
-            Some(str) => Some(str),
-            None => {
-                println!("Expected int argument: {}", name);
-                None
+            let from = command.get_string_arg("from")?;
+            if from == "clear" {
+                command.error_on_args()?;
+                lattice.set_transition_rates(TransitionRates::default());
+                return Some(());
             }
-        }?;
+            let to = command.get_string_arg("to")?;
+            let rate: Real = command.get_float_arg("rate")?;
+            command.error_on_args()?;
 
-        match arg.parse() {
-            Ok(int) => Some(int),
-            Err(_err) => {
-                println!("Expected int argument: {}", name);
-                None
+            let (from, to) = match (from.parse::<State>(), to.parse::<State>()) {
+                (Ok(from), Ok(to)) => (from, to),
+                (Err(_), _) => {
+                    println!("Unknown state: {}", from);
+                    return None;
+                },
+                (_, Err(_)) => {
+                    println!("Unknown state: {}", to);
+                    return None;
+                },
+            };
+            if !(rate >= 0.0 && rate.is_finite()) {
+                println!("Expected a non-negative rate, got {}", rate);
+                return None;
             }
+            if from == to {
+                println!("A state cannot transition to itself");
+                return None;
+            }
+
+            let mut transitions = lattice.transition_rates();
+            transitions.set(from, to, rate);
+            lattice.set_transition_rates(transitions);
         }
-    }
+        "schedule" => {
+            // Ensure there's a lattice
+            let Run { lattice, .. } = match lattice {
+                Some(ref mut stuff) => stuff,
+                None => {
+                    println!("Use \"init\" or \"load\" to create a lattice");
+                    return None;
+                },
+            };
 
-    /// Gets a string arg and prints an error message otherwise.
-    pub fn get_float_arg(&mut self, name: &str) -> Option<f32> {
-        let arg = match self.arg_iter.next() {
-            Some(str) => Some(str),
-            None => {
-                println!("Expected float argument: {}", name);
-                None
+            let path = command.get_string_arg("file")?;
+            if path == "clear" {
+                command.error_on_args()?;
+                lattice.set_payoff_schedule(None);
+                return Some(());
             }
-        }?;
+            let interpolation = match command.get_option("ramp") {
+                Some(arg) => match arg.parse() {
+                    Ok(resolution) => Interpolation::Staircase { resolution },
+                    Err(_) => {
+                        println!("Expected a float for the ramp resolution, got {}", arg);
+                        return None;
+                    }
+                },
+                None => Interpolation::Step,
+            };
+            let period = match command.get_option("period") {
+                Some(arg) => match arg.parse() {
+                    Ok(period) => Some(period),
+                    Err(_) => {
+                        println!("Expected a float for the period, got {}", arg);
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            command.error_on_args()?;
 
-        match arg.parse() {
-            Ok(int) => Some(int),
-            Err(_err) => {
-                println!("Expected float argument: {}", name);
-                None
+            let keyframes = load_schedule(&path)?;
+            let schedule = match PayoffSchedule::new(keyframes, interpolation, period) {
+                Ok(schedule) => schedule,
+                Err(err) => {
+                    println!("Invalid schedule: {}", err);
+                    return None;
+                }
+            };
+            lattice.set_payoff_schedule(Some(schedule));
+            if let Some(err) = lattice.rate_error() {
+                println!("Invalid rates: {}", err);
             }
         }
-    }
-
-    // pub fn get_payoff_matrix_arg(&mut self, name: &str) -> Option<PayoffMatrix> {
-    //     let a1 = self.get_float_arg("a1")?;
-    //     let a2 = self.get_float_arg("a2")?;
-    //     let a3 = self.get_float_arg("a3")?;
+        "regions" => {
+            // Ensure there's a lattice
+            let Run { lattice, .. } = match lattice {
+                Some(ref mut stuff) => stuff,
+                None => {
+                    println!("Use \"init\" or \"load\" to create a lattice");
+                    return None;
+                },
+            };
+
+            let map = command.get_string_arg("map")?;
+            if map == "clear" {
+                command.error_on_args()?;
+                lattice.set_payoff_regions(None).expect("every node plays by the lattice's matrix");
+                return Some(());
+            }
+            let matrices = load_matrices(&command.get_string_arg("matrices")?)?;
+            command.error_on_args()?;
+
+            let Some(grid) = lattice.grid() else {
+                println!("Regions can only be mapped onto a lattice");
+                return None;
+            };
+            let regions = match map.split_once(':') {
+                Some(("csv", path)) => {
+                    PayoffRegions::from_lattice(grid, &load_csv_regions(path)?, matrices)
+                },
+                Some(("depth", rule)) => {
+                    let (axis, thresholds) = parse_depth(rule)?;
+                    PayoffRegions::by_depth(grid, axis, &thresholds, matrices)
+                },
+                _ => {
+                    println!("Unknown region map: {}", map);
+                    return None;
+                }
+            };
+            if let Err(err) = regions.and_then(|regions| lattice.set_payoff_regions(Some(regions))) {
+                println!("Invalid regions: {}", err);
+                return None;
+            }
+            if let Some(err) = lattice.rate_error() {
+                println!("Invalid rates: {}", err);
+            }
+        }
+        "leap" => {
+
+            let time_step: f64 = command.get_float_arg("time_step")?;
+            let tolerance: f64 = command.get_float_arg("tolerance")?;
+            let log = command.get_option("log");
+            command.error_on_args()?;
+
+            if !(tolerance > 0.0 && tolerance <= 1.0) {
+                println!("Expected a tolerance between 0 and 1, got {}", tolerance);
+                return None;
+            }
+
+            // Ensure there's a lattice
+            let Run { lattice, step_buf, rng, check_every, .. } = match lattice {
+                Some(ref mut stuff) => stuff,
+                None => {
+                    println!("Use \"init\" or \"load\" to create a lattice");
+                    return None;
+                },
+            };
+            if lattice.kinetics() == Kinetics::Scan {
+                println!("Tau leaping needs direct kinetics");
+                return None;
+            }
+
+            // Each leap is logged as the time it ended, its length, the number
+            // of events and whether it was an exact step
+            let mut log = match log {
+                Some(path) => match OpenOptions::new().append(true).create(true).open(path) {
+                    Ok(file) => Some(BufWriter::new(file)),
+                    Err(err) => {
+                        println!("Error opening file: {}", err);
+                        return None;
+                    },
+                },
+                None => None,
+            };
+
+            let real_start = Instant::now();
+            let final_time = lattice.time + time_step;
+            let mut leaps: u32 = 0;
+            let mut exact: u32 = 0;
+            let mut events: usize = 0;
+            let mut tau_range = (f64::INFINITY, 0.0f64);
+            while lattice.time < final_time {
+
+                if ctrlc.load(std::sync::atomic::Ordering::Relaxed) {
+                    println!("Aborted; {} leaps completed and t = {}", leaps, lattice.time);
+                    ctrlc.store(false, std::sync::atomic::Ordering::Relaxed);
+                    return None;
+                }
+
+                let leap = match lattice.leap(tolerance, rng) {
+                    Ok(leap) => leap,
+                    Err(err) => {
+                        println!("Stopped after {} leaps: {}", leaps, err);
+                        return None;
+                    },
+                };
+                if let Some(ref mut log) = log {
+                    writeln!(log, "{},{},{},{}",
+                        lattice.time, leap.tau, leap.events.len(), leap.exact as u8).unwrap();
+                }
+
+                leaps += 1;
+                exact += leap.exact as u32;
+                events += leap.events.len();
+                if leap.tau.is_finite() {
+                    tau_range = (tau_range.0.min(leap.tau), tau_range.1.max(leap.tau));
+                }
+                step_buf.extend(leap.events.into_iter().map(|event| (lattice.time, event)));
+                if check_every.is_some_and(|every| (leaps as u64).is_multiple_of(every)) {
+                    report_divergences(lattice);
+                }
+            }
+
+            println!("Done; {} leaps ({} exact steps) made {} events to reach t = {:.5} in {}ms",
+                leaps, exact, events, lattice.time, real_start.elapsed().as_millis());
+            if leaps > exact {
+                println!("Leap sizes ranged from {} to {}, with {:.1} events per leap on average",
+                    tau_range.0, tau_range.1, events as f32 / leaps as f32);
+            }
+        }
+        "replicate" => {
+            let replicates = command.get_int_arg("replicates")?;
+            let path = std::path::PathBuf::from(command.get_string_arg("path")?);
+            let time: f64 = command.get_float_arg("time")?;
+            let samples = match command.get_option("samples") {
+                Some(arg) => match arg.parse::<u32>() {
+                    Ok(samples) if samples > 0 => samples,
+                    _ => {
+                        println!("Expected a positive number of samples, got {}", arg);
+                        return None;
+                    }
+                },
+                None => 1,
+            };
+            let threads = match command.get_option("threads") {
+                Some(arg) => match arg.parse::<usize>() {
+                    Ok(threads) if threads > 0 => threads,
+                    _ => {
+                        println!("Expected a positive number of threads, got {}", arg);
+                        return None;
+                    }
+                },
+                None => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            };
+            command.error_on_args()?;
+
+            let Run { lattice, seed, .. } = match lattice {
+                Some(ref mut stuff) => stuff,
+                None => {
+                    println!("Use \"init\" or \"load\" to create a lattice");
+                    return None;
+                },
+            };
+            let template: &BoneLattice = lattice;
+
+            if let Err(err) = std::fs::create_dir_all(&path) {
+                println!("Error creating folder: {}", err);
+                return None;
+            }
+
+            // Replicates draw their seeds from a stream of their own, and
+            // record them in their dumps so that each can be rerun alone with
+            // "init ... seed=<seed>"
+            let mut seed_rng = SimRng::seed_from_u64(*seed);
+            seed_rng.jump();
+            let seeds: Vec<u64> = (0..replicates.max(0)).map(|_| seed_rng.gen()).collect();
+
+            let real_start = Instant::now();
+            let next = std::sync::atomic::AtomicUsize::new(0);
+            let completed = std::sync::atomic::AtomicUsize::new(0);
+            std::thread::scope(|scope| {
+                for _ in 0..threads.min(seeds.len()) {
+                    scope.spawn(|| loop {
+                        let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        if i >= seeds.len() { break }
+                        let folder = path.join(i.to_string());
+                        if run_replicate(template, seeds[i], time, samples, &folder, &ctrlc).is_some() {
+                            println!("Replicate {} done", i);
+                            completed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        }
+                    });
+                }
+            });
+
+            if ctrlc.load(std::sync::atomic::Ordering::Relaxed) {
+                println!("Aborted");
+                ctrlc.store(false, std::sync::atomic::Ordering::Relaxed);
+                return None;
+            }
+            println!("Done; {} of {} replicates completed in {}ms",
+                completed.into_inner(),
+                seeds.len(),
+                real_start.elapsed().as_millis()
+            );
+        }
+        "bench" => {
+            let shape = parse_shape(&command.get_string_arg("shape")?)?;
+            let sweeps = command.get_int_arg("sweeps")?;
+            let neighborhood = match command.get_option("neighborhood") {
+                Some(arg) => parse_neighborhood(arg)?,
+                None => Neighborhood::moore(1),
+            };
+            command.error_on_args()?;
+
+            // Computing the fitness of every cell visits each neighbor of every
+            // cell, so it shows how well a layout keeps neighbors close. Only
+            // the sum over the neighbors is timed, without storing fitness or
+            // rates. Cells are visited both in storage order and in random
+            // order, which is how invasions touch them during a simulation
+            let layouts = [
+                Layout::RowMajor,
+                Layout::Tiled { edge: 4 },
+                Layout::Tiled { edge: 8 },
+                Layout::Tiled { edge: 16 },
+            ];
+            let mut rng = rand::thread_rng();
+            for layout in layouts {
+                let grid = Grid::new(shape, [Boundary::Periodic; 3], neighborhood.clone())
+                    .and_then(|grid| Ok(grid.with_layout(layout)?));
+                let grid = match grid {
+                    Ok(grid) => grid,
+                    Err(err) => {
+                        println!("Error creating lattice: {}", err);
+                        return None;
+                    }
+                };
+                let matrix = PayoffMatrix::by_params([0.0; 3], [0.0; 3], 0.1)
+                    .expect("payoffs are finite");
+                let bench_lattice = match BoneLattice::with_topology(
+                    Arc::new(grid), matrix, |_| rng.gen::<State>()
+                ) {
+                    Ok(x) => x,
+                    Err(err) => {
+                        println!("Error creating lattice: {}", err);
+                        return None;
+                    }
+                };
+
+                let random_order: Vec<usize> = (0..bench_lattice.len())
+                    .map(|_| rng.gen_range(0..bench_lattice.len()))
+                    .collect();
+
+                let mut elapsed = [std::time::Duration::ZERO; 2];
+                for i in 0..sweeps {
+                    if ctrlc.load(std::sync::atomic::Ordering::Relaxed) {
+                        println!("Aborted; {} sweeps completed", i);
+                        ctrlc.store(false, std::sync::atomic::Ordering::Relaxed);
+                        return None;
+                    }
+
+                    let start = Instant::now();
+                    let sum: Real = (0..bench_lattice.len())
+                        .map(|node| bench_lattice.compute_fitness(node))
+                        .sum();
+                    std::hint::black_box(sum);
+                    elapsed[0] += start.elapsed();
+
+                    let start = Instant::now();
+                    let sum: Real = random_order.iter()
+                        .map(|&node| bench_lattice.compute_fitness(node))
+                        .sum();
+                    std::hint::black_box(sum);
+                    elapsed[1] += start.elapsed();
+                }
+
+                let per_sweep = elapsed.map(|time| {
+                    time.as_secs_f64() * 1000.0 / sweeps.max(1) as f64
+                });
+                println!("{:?}: {:.3}ms per sweep in order, {:.3}ms per sweep in random order",
+                    layout, per_sweep[0], per_sweep[1]);
+            }
+        }
+        "help" => {
+            println!("List of all commands:");
+            println!("\texit");
+            println!("\t\tExits the simulator. THIS DISCARDS ANY UNSAVED DATA!!");
+            println!("\tinit <shape: int, int x int, int x int x int or graph:<file>> <alpha1: float> <alpha2: float> <alpha3: float> <beta1: float> <beta2: float> <beta3: float> [boundary=<boundary>[,<boundary>,<boundary>]] [neighborhood=<neighborhood>] [layout=<layout>] [fitness=<stored|computed>] [kinetics=<kinetics>] [update=<update rule>] [omega=<float>] [payoffs=<payoffs>] [selection=<selection>] [rates=<rate policy>] [seed=<seed: int>]");
+            println!("\t\tInitializes the lattice in a random state and sets up the payoff matrix");
+            println!("\t\tBoundaries are given for all axes or for x, y and z: periodic (default), reflecting, absorbing or fixed:<state>");
+            println!("\t\tGraphs are read from a file with one edge per line, given as two node ids");
+            println!("\t\tNeighborhoods are vonneumann[:<radius>] (default), moore[:<radius>] or custom:<file> with one x,y,z offset per line;");
+            println!("\t\tneither the radius nor any offset may reach as far as the extent of an axis");
+            println!("\t\tLayouts are rowmajor (default) or tiled[:<edge>], which stores cells in Morton-ordered tiles (edge 16 by default)");
+            println!("\t\tTiles keep neighbors close in memory, which speeds up fitness on lattices too large for the cache and with large neighborhoods; see \"bench\"");
+            println!("\t\tFitness is stored for every cell (default) or computed from the neighbors when needed, which only saves memory with kinetics=scan,");
+            println!("\t\tsince the other kinetics keep a running sum of rates that takes more memory than the fitness");
+            println!("\t\tA cell takes 1 byte for its state, 4 for stored fitness (8 with the f64 feature), 8 for the sums of direct kinetics");
+            println!("\t\tand 16 for those of rejectionfree kinetics, so the smallest runs use kinetics=scan fitness=computed at 1 byte per cell,");
+            println!("\t\tat the cost of computing the fitness of every cell on every step");
+            println!("\t\tKinetics are direct (default), which finds each event in logarithmic time, scan, which samples every cell on every step,");
+            println!("\t\tor rejectionfree, which finds events like direct but skips invasions of neighbors in the same state, reporting how many it skipped");
+            println!("\t\tPayoff matrix entries are 1 + omega * parameter, with omega 0.1 by default");
+            println!("\t\tPayoffs from the neighbors are accumulated (default) or averaged, and turned into fitness by a selection of identity (default), linear:<w> for 1 - w + w * payoff, or exp:<w> for exp(w * payoff)");
+            println!("\t\tUpdate rules are birthdeath (default), deathbirth, imitation or fermi:<temperature>");
+            println!("\t\tWhere fitness is used as a rate, negative fitness is an error with rates=reject (default), never spreads with rates=inactive,");
+            println!("\t\tor is raised to a minimum with rates=clamp:<min>; infinite or NaN payoffs and fitness are always errors, which stop the simulation");
+            println!("\t\tThe same seed always gives the same run; without one, a random seed is chosen");
+            println!("\tstep <steps: int>");
+            println!("\t\tPerforms the specified number of simulation steps");
+            println!("\tsim <time: float>");
+            println!("\t\tRuns the simulation for the provided amount of simulation time");
+            println!("\trun until <condition> [<condition> ...]");
+            println!("\t\tRuns the simulation until any of the conditions holds, then reports which one it was and when");
+            println!("\t\tConditions are extinct (any state has no cells), fixed:<state>, above:<state>:<count>, below:<state>:<count>,");
+            println!("\t\ttime:<time> (simulation time), wall:<seconds> (real time) and stationary:<tolerance>:<window>, which holds once");
+            println!("\t\tthe fraction of cells in each state has stayed within the tolerance of its value for the window of simulation time, which must be positive");
+            println!("\tleap <time: float> <tolerance: float> [log=<file>]");
+            println!("\t\tRuns the simulation for the provided amount of simulation time using approximate tau leaping");
+            println!("\t\tThe tolerance is the fraction of cells expected to invade or transition in each leap; smaller is more accurate");
+            println!("\t\tOnly birth-death updates leap; other update rules take exact steps");
+            println!("\t\tThe time, length, number of events and exactness of each leap are appended to the log file if one is given");
+            println!("\tcheck [every=<steps: int|off>]");
+            println!("\t\tRecomputes the fitness and event rate of every cell and reports any that differ from the stored values");
+            println!("\t\tWith every, also checks after that many steps (or leaps) of \"step\", \"sim\" and \"leap\", reporting only differences");
+            println!("\ttransition <from: state> <to: state> <rate: float>");
+            println!("\t\tSets the rate at which cells change from one state to another on their own, scheduled alongside the other events of the simulation");
+            println!("\t\tStates are given by number or name (resorption, formation or quiescence)");
+            println!("\ttransition clear");
+            println!("\t\tRemoves all spontaneous transitions");
+            println!("\tschedule <file: str> [ramp=<resolution: float>] [period=<time: float>]");
+            println!("\t\tChanges the payoff matrix over simulation time, following keyframes read from a file with one per line:");
+            println!("\t\ta time followed by alpha1, alpha2, alpha3, beta1, beta2, beta3 and optionally omega as for \"init\", or by the 9 payoffs row by row");
+            println!("\t\tEach matrix holds until the next keyframe, or with ramp, approaches it linearly in a staircase of pieces at most the resolution long,");
+            println!("\t\teach holding the matrix from its middle, of which there can be at most 1048576");
+            println!("\t\tWith period, the keyframes repeat every period from the first one; otherwise the last matrix holds forever");
+            println!("\t\tThe simulation stops at every change to update the rates, so steps and leaps never straddle one");
+            println!("\tschedule clear");
+            println!("\t\tKeeps the payoff matrix in force from now on");
+            println!("\tregions <map> <matrices: file>");
+            println!("\t\tGives regions of the lattice their own payoff matrices, read from a file with one per line given as for \"schedule\" without the time");
+            println!("\t\tCells in region 0 keep the matrix from \"init\" (and follow any schedule), and cells in region n use the nth matrix of the file");
+            println!("\t\tMaps are csv:<file> laid out like \"dump csv\" with the region of each cell, or depth:<axis>:<depth>[,<depth>...],");
+            println!("\t\twhich puts cells in the region numbered by how many of the depths their distance from the nearer face along the axis reaches");
+            println!("\tregions clear");
+            println!("\t\tMakes every cell use the matrix from \"init\" again");
+            println!("\tmask csv <file: str>");
+            println!("\t\tRestricts the simulation to the nonzero cells of a file laid out like \"dump csv\"");
+            println!("\tmask img <path: str>");
+            println!("\t\tRestricts the simulation to the non-black pixels of images laid out like \"dump img\"");
+            println!("\tmask clear");
+            println!("\t\tLets every cell take part in the simulation again");
+            println!("\ttime");
+            println!("\t\tPrints the current simulation time");
+            println!("\tseed");
+            println!("\t\tPrints the seed of the current run, which is also recorded in every dump");
+            println!("\tcount");
+            println!("\t\tPrints the number of cells in each state");
+            println!("\treplicate <replicates: int> <path: str> <time: float> [samples=<int>] [threads=<int>]");
+            println!("\t\tRuns independent copies of the current lattice from new random states in parallel, each for the provided amount of simulation time");
+            println!("\t\tReplicate i writes count.csv (sampled the given number of times), state.csv and img to <path>/<i>; each records the seed that reruns it");
+            println!("\tbench <shape> <sweeps: int> [neighborhood=<neighborhood>]");
+            println!("\t\tTimes summing the payoffs from the neighbors of every cell of a random lattice in each storage layout, in storage order and in random order");
+            println!("\tdump <type> <path> [region=<x>,<y>,<z>:<shape>]");
+            println!("\t\tCSV and steps dumps start with a \"# seed=<seed>\" line, count dumps end each line with the seed, and images store it in a \"seed\" text chunk");
+            println!("\t\tAll dumps except steps can be limited to the box of the given shape starting at x, y, z, which wraps around the edges of the lattice");
+            println!("\tdump csv <file: str>");
+            println!("\t\tCreates a new CSV file and saves the current lattice state to it");
+            println!("\t\tGraphs are saved with one node,state line per node");
+            println!("\tdump img <path: str>");
+            println!("\t\tPopulates the specified folder with an image representation fo the current lattice state");
+            println!("\t\tFor 1D and 2D lattices, the path is instead a single PNG file");
+            println!("\tdump count <file: str>");
+            println!("\t\tDumps the number of cells in each state to the provided file");
+            println!("\tdump steps <file: str>");
+            println!("\t\tPrints all the simulation steps made to the specified file, one per line as the time, the cell that acted, its new or passed-on state,");
+            println!("\t\tthe kind of step, and for invasions the cell that took the state, which is empty if the invasion reached no cell that could change");
+            println!("\t\tEach step is the time it happened (or the end of its leap) and the x,y,z index (or node id for graphs) of the invading cell and its state");
+            println!("\t\tfollowed by \"invasion\", or of the cell that changed state on its own and its new state followed by \"transition\"");
+            println!("\t\tTimes in count and steps dumps are written with full precision");
+        }
+        _ => {
+            println!("That command doesn't exist (type \"help\")");
+        }
+    };
+
+    Some(())
+}
+
+/// Takes a step and records what changed, printing why if the step fails.
+fn record_step(
+    lattice: &mut BoneLattice,
+    rng: &mut SimRng,
+    step_buf: &mut Vec<(f64, Event)>,
+) -> Option<()> {
+    match lattice.step(rng) {
+        Ok(event) => {
+            step_buf.extend(event.map(|event| (lattice.time, event)));
+            Some(())
+        },
+        Err(err) => {
+            println!("Stopped: {}", err);
+            None
+        },
+    }
+}
+
+/// Checks the stored values of a lattice against freshly computed ones and
+/// prints the cells that differ, returning how many there were.
+fn report_divergences(lattice: &BoneLattice) -> usize {
+    const SHOWN: usize = 10;
+
+    let divergences = lattice.check_fitness();
+    if divergences.is_empty() { return 0 }
+
+    println!("{} cells have stale fitness at t = {}", divergences.len(), lattice.time);
+    for divergence in divergences.iter().take(SHOWN) {
+        let cell = match lattice.grid() {
+            Some(grid) => {
+                let idx = grid.idx(divergence.node);
+                format!("{},{},{}", idx.0, idx.1, idx.2)
+            },
+            None => divergence.node.to_string(),
+        };
+        let stored = |value: Option<Real>| value.map_or("-".to_owned(), |value| value.to_string());
+        println!("\t{}: fitness {} stored, {} computed; rate {} stored, {} computed",
+            cell, stored(divergence.stored_fitness), divergence.fitness,
+            stored(divergence.stored_rate), divergence.rate);
+    }
+    if divergences.len() > SHOWN {
+        println!("\t...and {} more", divergences.len() - SHOWN);
+    }
+    divergences.len()
+}
+
+/// Runs a copy of `template` restarted from `seed` for `time` units of
+/// simulation time, appending counts to `count.csv` in `folder` at `samples`
+/// evenly spaced times and then writing the final states to `state.csv` and,
+/// for lattices, `img`. Returns [`None`] if the run fails or is aborted.
+fn run_replicate(
+    template: &BoneLattice,
+    seed: u64,
+    time: f64,
+    samples: u32,
+    folder: &std::path::Path,
+    ctrlc: &AtomicBool,
+) -> Option<()> {
+    if let Err(err) = std::fs::create_dir(folder) {
+        println!("Error creating folder: {}", err);
+        return None;
+    }
+
+    let mut rng = SimRng::seed_from_u64(seed);
+    let mut lattice = template.clone();
+    lattice.restart(&mut rng);
+
+    let count_file = folder.join("count.csv");
+    for sample in 1..=samples {
+        let sample_time = time * sample as f64 / samples as f64;
+        while lattice.time < sample_time {
+            if ctrlc.load(std::sync::atomic::Ordering::Relaxed) {
+                return None;
+            }
+            if let Err(err) = lattice.step(&mut rng) {
+                println!("Replicate with seed {} stopped: {}", seed, err);
+                return None;
+            }
+        }
+        dump_count(&lattice, count_file.to_str()?, None, seed)?;
+    }
+
+    dump_csv(&lattice, folder.join("state.csv").to_str()?, None, seed)?;
+    if let Some(grid) = lattice.grid() {
+        // 3D lattices are drawn as a folder of layers
+        let img = match grid.dimension() {
+            3 => {
+                let img = folder.join("img");
+                if let Err(err) = std::fs::create_dir(&img) {
+                    println!("Error creating folder: {}", err);
+                    return None;
+                }
+                img
+            },
+            _ => folder.join("img.png"),
+        };
+        dump_img(&lattice, img.to_str()?, None, seed)?;
+    }
+    Some(())
+}
+
+/// Writes the states of a lattice to a new CSV file, as blocks of rows for
+/// lattices and one line per node for other topologies.
+fn dump_csv(
+    lattice: &BoneLattice,
+    file: &str,
+    region: Option<(LatticeIdx, LatticeIdx)>,
+    seed: u64,
+) -> Option<()> {
+    // Lattices are written as blocks of rows, and any other topology as one
+    // line per node
+    let states = match (lattice.grid(), region) {
+        (None, None) => None,
+        _ => Some(snapshot(lattice, region)?),
+    };
+
+    let file_result = new_file_options().open(file);
+    let mut file = match file_result {
+        Ok(x) => x,
+        Err(err) => {
+            println!("Error opening file: {}", err);
+            return None;
+        },
+    };
+
+    writeln!(file, "# seed={}", seed).unwrap();
+    match states {
+        Some(states) => {
+            let shape = states.shape();
+            for i in 0..shape.0 {
+                for j in 0..shape.1 {
+                    for k in 0..shape.2 {
+                        // Cells outside the domain are left empty
+                        if let Some(state) = states[LatticeIdx(i, j, k)] {
+                            write!(file, "{}", state_number(state)).unwrap();
+                        }
+                        write!(file, ",").unwrap();
+                    }
+                    writeln!(file).unwrap();
+                }
+                writeln!(file).unwrap();
+            }
+        },
+        None => {
+            for node in 0..lattice.len() {
+                writeln!(file, "{},{}", node, state_number(*lattice.state(node))).unwrap();
+            }
+        },
+    }
+    Some(())
+}
+
+/// Appends the time and the number of cells in each state to a CSV file.
+fn dump_count(
+    lattice: &BoneLattice,
+    file: &str,
+    region: Option<(LatticeIdx, LatticeIdx)>,
+    seed: u64,
+) -> Option<()> {
+    let file_result = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(file);
+    let mut file = match file_result {
+        Ok(x) => x,
+        Err(err) => {
+            println!("Error opening file: {}", err);
+            return None;
+        },
+    };
+    let count = match region {
+        Some(_) => count_states(&snapshot(lattice, region)?),
+        None => lattice.count(),
+    };
+    writeln!(file, "{},{},{},{},{}", lattice.time, count.0, count.1, count.2, seed).unwrap();
+    Some(())
+}
+
+/// Draws a lattice as a single PNG file, or as a folder of one PNG per x
+/// layer for 3D lattices.
+fn dump_img(
+    lattice: &BoneLattice,
+    file: &str,
+    region: Option<(LatticeIdx, LatticeIdx)>,
+    seed: u64,
+) -> Option<()> {
+    if lattice.grid().is_none() {
+        println!("Only lattices can be dumped as images");
+        return None;
+    }
+
+    let open_options = new_file_options();
+    let path = std::path::PathBuf::from(file);
+    let states = snapshot(lattice, region)?;
+    let shape = states.shape();
+
+    if states.dimension() < 3 {
+        // Lay the active axes out along the width and height of a single
+        // image
+        let mut axes = shape.active_axes();
+        let width_axis = axes.next().unwrap_or(0);
+        let height_axis = axes.next();
+        let img = ImageBuffer::from_fn(
+            shape.axis(width_axis) as u32,
+            height_axis.map_or(1, |axis| shape.axis(axis)) as u32,
+            |i, j| {
+                let mut idx = LatticeIdx(0, 0, 0);
+                *idx.axis_mut(width_axis) = i as i16;
+                if let Some(axis) = height_axis {
+                    *idx.axis_mut(axis) = j as i16;
+                }
+                state_color(states[idx])
+            }
+        );
+        write_png(&img, &path, &open_options, seed)?;
+    } else {
+        for img_idx in 0..shape.0 {
+            let img = ImageBuffer::from_fn(
+                shape.1 as u32, shape.2 as u32,
+                |i, j| {
+                    state_color(states[LatticeIdx(img_idx, i as i16, j as i16)])
+                }
+            );
+            write_png(&img, &path.join(format!("layer{}.png", img_idx)), &open_options, seed)?;
+        }
+    }
+    Some(())
+}
+
+/// Options for opening a file that must not exist yet.
+fn new_file_options() -> OpenOptions {
+    let mut open_options = OpenOptions::new();
+    open_options
+        .write(true)
+        .create_new(true);
+    open_options
+}
+
+/// Copies the states of the whole lattice, or only those in a region if one is
+/// given.
+fn snapshot(
+    lattice: &BoneLattice,
+    region: Option<(LatticeIdx, LatticeIdx)>
+) -> Option<Lattice<Option<State>>> {
+    let shape = lattice.grid().map_or(LatticeIdx(0, 0, 0), |grid| grid.shape());
+    let (low, shape) = region.unwrap_or((LatticeIdx(0, 0, 0), shape));
+    match lattice.region(low, shape) {
+        Ok(states) => Some(states),
+        Err(err) => {
+            println!("Invalid region: {}", err);
+            None
+        }
+    }
+}
+
+/// Counts the cells of a snapshot in each state, like [`BoneLattice::count`].
+fn count_states(states: &Lattice<Option<State>>) -> (usize, usize, usize) {
+    let mut count = (0, 0, 0);
+    for idx in LatticeIdx::shape_iter(states.shape()) {
+        match states[idx] {
+            Some(State::Resorption) => count.0 += 1,
+            Some(State::Formation) => count.1 += 1,
+            Some(State::Quiescence) => count.2 += 1,
+            None => {},
+        }
+    }
+    count
+}
+
+/// Gets the number a state is written as in dumps.
+fn state_number(state: State) -> u8 {
+    state as u8
+}
+
+/// Gets the color a cell is drawn with in image dumps, with cells outside the
+/// domain drawn black.
+fn state_color(state: Option<State>) -> Rgb<u8> {
+    match state {
+        Some(State::Resorption) => Rgb([0, 0, 255]),
+        Some(State::Formation) => Rgb([150, 200, 150]),
+        Some(State::Quiescence) => Rgb([255, 255, 0]),
+        None => Rgb([0, 0, 0]),
+    }
+}
+
+/// Loads a domain mask from a file in the layout written by "dump csv", where
+/// empty and zero values are outside the domain.
+fn load_csv_mask(path: &str) -> Option<Lattice<bool>> {
+    load_csv_lattice(path, "mask", |value| Some(!matches!(value, "" | "0")))
+}
+
+/// Loads a region map from a file in the layout written by "dump csv", where
+/// each value is the region of a cell and empty values are region 0.
+fn load_csv_regions(path: &str) -> Option<Lattice<u8>> {
+    load_csv_lattice(path, "region map", |value| match value {
+        "" => Some(0),
+        value => value.parse().ok(),
+    })
+}
+
+/// Loads a lattice of values from a file in the layout written by "dump csv",
+/// turning each trimmed value into a cell with `parse`. `what` names the
+/// lattice in errors.
+fn load_csv_lattice<T: Copy, F: Fn(&str) -> Option<T>>(
+    path: &str,
+    what: &str,
+    parse: F,
+) -> Option<Lattice<T>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(err) => {
+            println!("Error reading file: {}", err);
+            return None;
+        },
+    };
+
+    // Lines starting with # hold the seed of the run that wrote the file
+    let contents: String = contents.lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| format!("{}\n", line))
+        .collect();
+
+    // Blocks separated by blank lines are x layers, lines are y rows, and
+    // values are z columns
+    let blocks: Option<Vec<Vec<Vec<T>>>> = contents
+        .split("\n\n")
+        .filter(|block| !block.trim().is_empty())
+        .map(|block| {
+            block.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| {
+                    let line = line.trim_end();
+                    line.strip_suffix(',')
+                        .unwrap_or(line)
+                        .split(',')
+                        .map(|value| parse(value.trim()))
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+    let Some(blocks) = blocks else {
+        println!("The {} has a value that is not valid", what);
+        return None;
+    };
+
+    let extents = [
+        blocks.len() as i64,
+        blocks.first().map_or(0, Vec::len) as i64,
+        blocks.first().and_then(|rows| rows.first()).map_or(0, Vec::len) as i64,
+    ];
+    let consistent = blocks.iter().all(|rows| {
+        rows.len() as i64 == extents[1]
+            && rows.iter().all(|row| row.len() as i64 == extents[2])
+    });
+    if !consistent {
+        println!("Every layer of the {} must have the same number of rows and columns", what);
+        return None;
+    }
+
+    let shape = match LatticeIdx::try_shape(extents) {
+        Ok(x) => x,
+        Err(err) => {
+            println!("Invalid {} shape: {}", what, err);
+            return None;
+        }
+    };
+    Lattice::filled(shape, |idx| {
+        blocks[idx.0 as usize][idx.1 as usize][idx.2 as usize]
+    }).ok()
+}
+
+/// Loads a domain mask from images in the layout written by "dump img", where
+/// black pixels are outside the domain. A folder is read as a stack of
+/// layer{n}.png files, and any other path as a single image of a 2D lattice.
+fn load_img_mask(path: &str) -> Option<Lattice<bool>> {
+    let path = std::path::Path::new(path);
+    let layer_paths: Vec<_> = if path.is_dir() {
+        (0..)
+            .map(|idx| path.join(format!("layer{}.png", idx)))
+            .take_while(|layer| layer.exists())
+            .collect()
+    } else {
+        vec![path.to_owned()]
+    };
+
+    let mut layers = Vec::with_capacity(layer_paths.len());
+    for layer in layer_paths {
+        match image::open(&layer) {
+            Ok(img) => layers.push(img.to_luma8()),
+            Err(err) => {
+                println!("Error reading image {}: {}", layer.display(), err);
+                return None;
+            }
+        }
+    }
+
+    let (width, height) = layers.first().map_or((0, 0), |layer| layer.dimensions());
+    if layers.iter().any(|layer| layer.dimensions() != (width, height)) {
+        println!("Every layer of the mask must have the same dimensions");
+        return None;
+    }
+
+    // A single image holds the two axes of a 2D lattice, whereas a stack holds
+    // one x layer per image
+    let extents = if path.is_dir() {
+        [layers.len() as i64, width as i64, height as i64]
+    } else {
+        [width as i64, height as i64, 1]
+    };
+    let shape = match LatticeIdx::try_shape(extents) {
+        Ok(x) => x,
+        Err(err) => {
+            println!("Invalid mask shape: {}", err);
+            return None;
+        }
+    };
+    Lattice::filled(shape, |idx| {
+        let pixel = if path.is_dir() {
+            layers[idx.0 as usize].get_pixel(idx.1 as u32, idx.2 as u32)
+        } else {
+            layers[0].get_pixel(idx.0 as u32, idx.1 as u32)
+        };
+        pixel.0[0] > 0
+    }).ok()
+}
+
+/// Writes an image to a newly created PNG file, recording the seed of the run
+/// in a text chunk and printing an error on failure.
+fn write_png(
+    img: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    path: &std::path::Path,
+    open_options: &OpenOptions,
+    seed: u64,
+) -> Option<()> {
+    let writer = match open_options.open(path) {
+        Ok(x) => x,
+        Err(err) => {
+            println!("Error opening file: {}", err);
+            return None;
+        }
+    };
+    let mut encoder = png::Encoder::new(BufWriter::new(writer), img.width(), img.height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let result = encoder.add_text_chunk("seed".to_string(), seed.to_string())
+        .and_then(|_| encoder.write_header())
+        .and_then(|mut writer| writer.write_image_data(img.as_raw()));
+    match result {
+        Ok(_) => Some(()),
+        Err(err) => {
+            println!("Error writing image: {}", err);
+            None
+        },
+    }
+}
+
+/// Parses the shape of a lattice, either as a single side length for a cube or
+/// as the extent along each axis separated by "x" (such as 256x256x8). Giving
+/// two extents makes a 2D lattice; an extent of 1 removes an axis entirely, so
+/// 1D lattices are written as 64x1.
+fn parse_shape(arg: &str) -> Option<LatticeIdx> {
+    let parsed = arg.split('x')
+        .map(|extent| extent.parse::<i64>().ok())
+        .collect::<Option<Vec<_>>>();
+
+    let extents = match parsed.as_deref() {
+        Some(&[size]) => [size; 3],
+        Some(&[x, y]) => [x, y, 1],
+        Some(&[x, y, z]) => [x, y, z],
+        _ => {
+            println!("Expected shape argument: shape");
+            return None;
+        }
+    };
+
+    match LatticeIdx::try_shape(extents) {
+        Ok(shape) => Some(shape),
+        Err(err) => {
+            println!("Invalid shape: {}", err);
+            None
+        }
+    }
+}
+
+/// Loads an undirected graph from a file with one edge per line, given as two
+/// node ids separated by whitespace or a comma. Lines starting with "#" are
+/// comments, and the graph has as many nodes as the largest id plus one.
+fn load_graph(path: &str) -> Option<Graph> {
+    let file = match File::open(path) {
+        Ok(x) => x,
+        Err(err) => {
+            println!("Error opening file: {}", err);
+            return None;
+        },
+    };
+
+    let mut edges = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(x) => x,
+            Err(err) => {
+                println!("Error reading file: {}", err);
+                return None;
+            },
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue }
+
+        let nodes = line.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|node| !node.is_empty())
+            .map(|node| node.parse::<usize>().ok())
+            .collect::<Option<Vec<_>>>();
+        match nodes.as_deref() {
+            Some(&[a, b]) => edges.push((a, b)),
+            _ => {
+                println!("Expected an edge as two node ids, got {}", line);
+                return None;
+            }
+        }
+    }
+
+    let len = edges.iter().map(|&(a, b)| a.max(b) + 1).max().unwrap_or(0);
+    match Graph::from_edges(len, &edges) {
+        Ok(graph) => Some(graph),
+        Err(err) => {
+            println!("Error creating graph: {}", err);
+            None
+        }
+    }
+}
+
+/// Reads the keyframes of a payoff schedule from a file with one keyframe per
+/// line: a time followed by a payoff matrix as read by [`parse_matrix`].
+fn load_schedule(path: &str) -> Option<Vec<(f64, PayoffMatrix)>> {
+    let mut keyframes = Vec::new();
+    for fields in read_fields(path)? {
+        let Some(time) = fields.first().and_then(|time| time.parse::<f64>().ok()) else {
+            println!("Expected a keyframe to start with a time, got {}", fields.join(" "));
+            return None;
+        };
+        keyframes.push((time, parse_matrix(&fields[1..])?));
+    }
+    Some(keyframes)
+}
+
+/// Reads payoff matrices from a file with one matrix per line, as read by
+/// [`parse_matrix`].
+fn load_matrices(path: &str) -> Option<Vec<PayoffMatrix>> {
+    read_fields(path)?.iter().map(|fields| parse_matrix(fields)).collect()
+}
+
+/// Reads the comma or whitespace separated fields of each line of a file,
+/// skipping blank lines and lines starting with #.
+fn read_fields(path: &str) -> Option<Vec<Vec<String>>> {
+    let file = match File::open(path) {
+        Ok(x) => x,
+        Err(err) => {
+            println!("Error opening file: {}", err);
+            return None;
+        },
+    };
+
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(x) => x,
+            Err(err) => {
+                println!("Error reading file: {}", err);
+                return None;
+            },
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue }
+
+        lines.push(line.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|field| !field.is_empty())
+            .map(String::from)
+            .collect());
+    }
+    Some(lines)
+}
+
+/// Parses a payoff matrix from alpha1, alpha2, alpha3, beta1, beta2, beta3
+/// and optionally omega as for "init", or from its nine entries row by row.
+fn parse_matrix(fields: &[String]) -> Option<PayoffMatrix> {
+    let line = fields.join(" ");
+    let values = fields.iter()
+        .map(|value| value.parse::<Real>().ok())
+        .collect::<Option<Vec<_>>>();
+    let matrix = match values.as_deref() {
+        Some(&[a1, a2, a3, b1, b2, b3]) => {
+            PayoffMatrix::by_params([a1, a2, a3], [b1, b2, b3], 0.1)
+        },
+        Some(&[a1, a2, a3, b1, b2, b3, omega]) => {
+            PayoffMatrix::by_params([a1, a2, a3], [b1, b2, b3], omega)
+        },
+        Some(&[r1, r2, r3, f1, f2, f3, q1, q2, q3]) => {
+            PayoffMatrix::new([r1, r2, r3], [f1, f2, f3], [q1, q2, q3])
+        },
+        _ => {
+            println!("Expected 6 or 7 parameters or 9 payoffs, got {}", line);
+            return None;
+        }
+    };
+    match matrix {
+        Ok(matrix) => Some(matrix),
+        Err(err) => {
+            println!("Invalid payoff matrix {}: {}", line, err);
+            None
+        },
+    }
+}
+
+/// Parses the axis and thresholds of a depth rule for regions, such as
+/// z:2,5.
+fn parse_depth(arg: &str) -> Option<(usize, Vec<i16>)> {
+    let (axis, thresholds) = arg.split_once(':').unwrap_or((arg, ""));
+    let axis = match axis {
+        "x" => 0,
+        "y" => 1,
+        "z" => 2,
+        _ => {
+            println!("Unknown axis: {}", axis);
+            return None;
+        }
+    };
+    let thresholds = thresholds.split(',')
+        .filter(|threshold| !threshold.is_empty())
+        .map(|threshold| threshold.parse().ok())
+        .collect::<Option<Vec<_>>>();
+    match thresholds {
+        Some(thresholds) => Some((axis, thresholds)),
+        None => {
+            println!("Expected depths as integers separated by commas, got {}", arg);
+            None
+        }
+    }
+}
+
+/// Parses a region as the index of its low corner and its shape, such as
+/// 10,10,0:64x64x8.
+fn parse_region(arg: &str) -> Option<(LatticeIdx, LatticeIdx)> {
+    let (low, shape) = match arg.split_once(':') {
+        Some(x) => x,
+        None => {
+            println!("Expected region as <x>,<y>,<z>:<shape>");
+            return None;
+        }
+    };
+
+    let coords = low.split(',')
+        .map(|coord| coord.parse::<i16>().ok())
+        .collect::<Option<Vec<_>>>();
+    let low = match coords.as_deref() {
+        Some(&[x, y, z]) => LatticeIdx(x, y, z),
+        _ => {
+            println!("Expected region corner as <x>,<y>,<z>, got {}", low);
+            return None;
+        }
+    };
+
+    Some((low, parse_shape(shape)?))
+}
+
+/// Parses the boundary condition of every axis, either as a single boundary
+/// applied to all three or as a comma-separated list of three.
+fn parse_boundaries(arg: &str) -> Option<[Boundary<State>; 3]> {
+    let parsed = arg.split(',')
+        .map(parse_boundary)
+        .collect::<Option<Vec<_>>>()?;
+
+    match parsed[..] {
+        [all] => Some([all; 3]),
+        [x, y, z] => Some([x, y, z]),
+        _ => {
+            println!("Expected one boundary or three comma-separated boundaries");
+            None
+        }
+    }
+}
+
+fn parse_boundary(arg: &str) -> Option<Boundary<State>> {
+    match arg.split_once(':') {
+        None if arg == "periodic" => Some(Boundary::Periodic),
+        None if arg == "reflecting" => Some(Boundary::Reflecting),
+        None if arg == "absorbing" => Some(Boundary::Absorbing),
+        Some(("fixed", state)) => match state.parse() {
+            Ok(state) => Some(Boundary::Fixed(state)),
+            Err(()) => {
+                println!("Unknown state: {}", state);
+                None
+            }
+        },
+        _ => {
+            println!("Unknown boundary: {}", arg);
+            None
+        }
+    }
+}
+
+/// Parses a storage layout, which is either row-major or tiled with an optional
+/// tile edge.
+fn parse_layout(arg: &str) -> Option<Layout> {
+    match arg.split_once(':') {
+        None if arg == "rowmajor" => Some(Layout::RowMajor),
+        None if arg == "tiled" => Some(Layout::Tiled { edge: 16 }),
+        Some(("tiled", edge)) => match edge.parse() {
+            Ok(edge) => Some(Layout::Tiled { edge }),
+            Err(_) => {
+                println!("Expected a tile edge, got {}", edge);
+                None
+            }
+        },
+        _ => {
+            println!("Unknown layout: {}", arg);
+            None
+        }
+    }
+}
+
+/// Parses a selection, which is either the identity or a kind with an
+/// intensity after a colon.
+fn parse_selection(arg: &str) -> Option<Selection> {
+    let (kind, w) = match arg.split_once(':') {
+        None if arg == "identity" => return Some(Selection::Identity),
+        Some((kind, w)) => (kind, w),
+        None => {
+            println!("Unknown selection: {}", arg);
+            return None;
+        }
+    };
+    let w = match w.parse::<Real>() {
+        Ok(w) if w.is_finite() => w,
+        _ => {
+            println!("Expected a selection intensity, got {}", w);
+            return None;
+        }
+    };
+    match kind {
+        "linear" => Some(Selection::Linear { w }),
+        "exp" => Some(Selection::Exponential { w }),
+        _ => {
+            println!("Unknown selection: {}", arg);
+            None
+        }
+    }
+}
+
+fn parse_update_rule(arg: &str) -> Option<UpdateRule> {
+    match arg.split_once(':') {
+        None if arg == "birthdeath" => Some(UpdateRule::BirthDeath),
+        None if arg == "deathbirth" => Some(UpdateRule::DeathBirth),
+        None if arg == "imitation" => Some(UpdateRule::Imitation),
+        Some(("fermi", temperature)) => match temperature.parse::<Real>() {
+            Ok(temperature) if temperature > 0.0 => Some(UpdateRule::Fermi { temperature }),
+            _ => {
+                println!("Expected a positive temperature, got {}", temperature);
+                None
+            }
+        },
+        _ => {
+            println!("Unknown update rule: {}", arg);
+            None
+        }
+    }
+}
+
+fn parse_stop_condition(arg: &str) -> Option<StopCondition> {
+    let mut parts = arg.split(':');
+    let kind = parts.next().unwrap_or_default();
+    let params: Vec<_> = parts.collect();
+
+    // Every parameter of a condition is a state, a count or a number
+    let state = |param: &str| match param.parse::<State>() {
+        Ok(state) => Some(state),
+        Err(_) => {
+            println!("Unknown state: {}", param);
+            None
+        }
+    };
+    let count = |param: &str| match param.parse::<usize>() {
+        Ok(count) => Some(count),
+        Err(_) => {
+            println!("Expected a cell count, got {}", param);
+            None
+        }
+    };
+    let number = |param: &str| match param.parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Some(number),
+        _ => {
+            println!("Expected a non-negative number, got {}", param);
+            None
+        }
+    };
+
+    match (kind, &params[..]) {
+        ("extinct", []) => Some(StopCondition::Extinct),
+        ("fixed", [s]) => Some(StopCondition::Fixed(state(s)?)),
+        ("above", [s, c]) => Some(StopCondition::Above { state: state(s)?, count: count(c)? }),
+        ("below", [s, c]) => Some(StopCondition::Below { state: state(s)?, count: count(c)? }),
+        ("time", [t]) => Some(StopCondition::Time(number(t)?)),
+        ("wall", [t]) => match Duration::try_from_secs_f64(number(t)?) {
+            Ok(duration) => Some(StopCondition::WallClock(duration)),
+            Err(_) => {
+                println!("Expected a duration in seconds, got {}", t);
+                None
+            }
+        },
+        ("stationary", [tolerance, window]) => match number(window)? {
+            window if window > 0.0 => Some(StopCondition::Stationary { tolerance: number(tolerance)?, window }),
+            _ => {
+                println!("Expected a positive window, got {}", window);
+                None
+            }
+        },
+        _ => {
+            println!("Unknown stopping condition: {}", arg);
+            None
+        }
+    }
+}
+
+fn parse_rate_policy(arg: &str) -> Option<RatePolicy> {
+    match arg.split_once(':') {
+        None if arg == "inactive" => Some(RatePolicy::Inactive),
+        None if arg == "reject" => Some(RatePolicy::Reject),
+        Some(("clamp", min)) => match min.parse::<Real>() {
+            Ok(min) if min > 0.0 && min.is_finite() => Some(RatePolicy::Clamp { min }),
+            _ => {
+                println!("Expected a positive minimum rate, got {}", min);
+                None
+            }
+        },
+        _ => {
+            println!("Unknown rate policy: {}", arg);
+            None
+        }
+    }
+}
+
+fn parse_kinetics(arg: &str) -> Option<Kinetics> {
+    match arg {
+        "direct" => Some(Kinetics::Direct),
+        "scan" => Some(Kinetics::Scan),
+        "rejectionfree" => Some(Kinetics::RejectionFree),
+        _ => {
+            println!("Unknown kinetics: {}", arg);
+            None
+        }
+    }
+}
+
+/// Parses a neighborhood, which is either a named stencil with an optional
+/// radius or a file of custom offsets.
+fn parse_neighborhood(arg: &str) -> Option<Neighborhood> {
+    let (kind, param) = match arg.split_once(':') {
+        Some((kind, param)) => (kind, Some(param)),
+        None => (arg, None),
+    };
+
+    let radius = || match param.map(str::parse::<i16>) {
+        None => Some(1),
+        Some(Ok(radius)) if radius > 0 => Some(radius),
+        Some(_) => {
+            println!("Expected a positive radius, got {}", param.unwrap());
+            None
+        }
+    };
+
+    match (kind, param) {
+        ("vonneumann", _) => Some(Neighborhood::von_neumann(radius()?)),
+        ("moore", _) => Some(Neighborhood::moore(radius()?)),
+        ("custom", Some(path)) => {
+            let file = match File::open(path) {
+                Ok(x) => x,
+                Err(err) => {
+                    println!("Error opening file: {}", err);
+                    return None;
+                },
+            };
+
+            let mut offsets = Vec::new();
+            for line in BufReader::new(file).lines() {
+                let line = match line {
+                    Ok(x) => x,
+                    Err(err) => {
+                        println!("Error reading file: {}", err);
+                        return None;
+                    },
+                };
+                if line.trim().is_empty() { continue }
+                let coords = line.split(',')
+                    .map(|coord| coord.trim().parse::<i16>().ok())
+                    .collect::<Option<Vec<_>>>();
+                match coords.as_deref() {
+                    Some(&[x, y, z]) => offsets.push(LatticeIdx(x, y, z)),
+                    _ => {
+                        println!("Expected an x,y,z offset, got {}", line);
+                        return None;
+                    }
+                }
+            }
+
+            let neighborhood = Neighborhood::custom(offsets);
+            if neighborhood.is_none() {
+                println!("A neighborhood needs at least one offset and may not contain 0,0,0");
+            }
+            neighborhood
+        },
+        _ => {
+            println!("Unknown neighborhood: {}", arg);
+            None
+        }
+    }
+}
+
+/// A processed command issued by the user
+struct UserCommand<'a> {
+    pub identifier: &'a str,
+    arg_iter: std::vec::IntoIter<&'a str>,
+    options: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> UserCommand<'a, > {
+    pub fn new(command: &'a str) -> Option<Self> {
+        let mut args = command.split(' ');
+        args.next().map(|identifier| {
+            // Arguments of the form key=value are options and may appear
+            // anywhere after the identifier
+            let (options, args): (Vec<_>, Vec<_>) = args
+                .partition(|arg| arg.contains('='));
+            UserCommand {
+                identifier,
+                arg_iter: args.into_iter(),
+                options: options.into_iter()
+                    .filter_map(|arg| arg.split_once('='))
+                    .collect(),
+            }
+        })
+    }
+
+    /// Takes the value of an option if the user provided it.
+    pub fn get_option(&mut self, name: &str) -> Option<&'a str> {
+        let pos = self.options.iter().position(|(key, _)| *key == name)?;
+        Some(self.options.remove(pos).1)
+    }
+ 
+    /// Gets a string arg and prints an error message otherwise.
+    pub fn get_string_arg(&mut self, name: &str) -> Option<String> {
+        match self.arg_iter.next() {
+            Some(str) => Some(str.to_owned()),
+            None => {
+                println!("Expected string argument: {}", name);
+                None
+            }
+        }
+    }
+
+    /// Gets a string arg and prints an error message otherwise.
+    pub fn get_int_arg(&mut self, name: &str) -> Option<i64> {
+        let arg = match self.arg_iter.next() {
+            Some(str) => Some(str),
+            None => {
+                println!("Expected int argument: {}", name);
+                None
+            }
+        }?;
+
+        match arg.parse() {
+            Ok(int) => Some(int),
+            Err(_err) => {
+                println!("Expected int argument: {}", name);
+                None
+            }
+        }
+    }
+
+    /// Gets a string arg and prints an error message otherwise.
+    pub fn get_float_arg<F: FromStr>(&mut self, name: &str) -> Option<F> {
+        let arg = match self.arg_iter.next() {
+            Some(str) => Some(str),
+            None => {
+                println!("Expected float argument: {}", name);
+                None
+            }
+        }?;
+
+        match arg.parse() {
+            Ok(int) => Some(int),
+            Err(_err) => {
+                println!("Expected float argument: {}", name);
+                None
+            }
+        }
+    }
+
+    // pub fn get_payoff_matrix_arg(&mut self, name: &str) -> Option<PayoffMatrix> {
+    //     let a1 = self.get_float_arg("a1")?;
+    //     let a2 = self.get_float_arg("a2")?;
+    //     let a3 = self.get_float_arg("a3")?;
     //     match &*self.get_string_arg("/")? {
     //         "/" => Some(()),
     //         other => {
@@ -491,9 +2101,18 @@ impl<'a> UserCommand<'a, > {
     //     Some(PayoffMatrix::new([a1, a2, a3], [b1, b2, b3], [c1, c2, c3]))
     // }
 
+    /// Takes every remaining argument.
+    pub fn take_rest(&mut self) -> Vec<&'a str> {
+        self.arg_iter.by_ref().collect()
+    }
+
     /// Ensures that there are no more arguments, errors with [`None`] and an
     /// error message otherwise.
     pub fn error_on_args(&mut self) -> Option<()> {
+        if let Some((key, _)) = self.options.first() {
+            println!("Unknown option: {}", key);
+            return None;
+        }
         match self.arg_iter.next() {
             Some(_arg) => {
                 println!("Too many arguments in command");
@@ -0,0 +1,104 @@
+use crate::bone_lattice::InitError;
+use crate::lattice::LatticeIdx;
+
+/// The set of offsets from a cell to each of its neighbors. This is shared by
+/// the fitness computation and the choice of which neighbor to invade.
+///
+/// Named stencils are only built once the shape of the lattice is known, so
+/// that a radius too large for it is rejected before any offsets are made.
+#[derive(Debug, Clone)]
+pub struct Neighborhood {
+    stencil: Stencil,
+}
+
+#[derive(Debug, Clone)]
+enum Stencil {
+    VonNeumann(i16),
+    Moore(i16),
+    Custom(Vec<LatticeIdx>),
+}
+
+impl Neighborhood {
+    /// Cells within a Manhattan distance of `radius`; a radius of 1 gives the
+    /// six face-adjacent neighbors.
+    pub fn von_neumann(radius: i16) -> Self {
+        Self { stencil: Stencil::VonNeumann(radius) }
+    }
+
+    /// Cells within a Chebyshev distance of `radius`; a radius of 1 gives the
+    /// 26 cells of the surrounding 3x3x3 cube.
+    pub fn moore(radius: i16) -> Self {
+        Self { stencil: Stencil::Moore(radius) }
+    }
+
+    /// Uses the provided offsets as-is. Returns [`None`] if there are no
+    /// offsets or if a cell would be its own neighbor.
+    pub fn custom(offsets: Vec<LatticeIdx>) -> Option<Self> {
+        if offsets.is_empty() || offsets.contains(&LatticeIdx(0, 0, 0)) {
+            return None;
+        }
+        Some(Self { stencil: Stencil::Custom(offsets) })
+    }
+
+    /// Gets the offsets of this neighborhood on a lattice of the provided
+    /// shape. Offsets that move along an axis the shape collapses are
+    /// dropped, giving the equivalent neighborhood in fewer dimensions. Fails
+    /// if an offset reaches as far as the extent of its axis, which would
+    /// make a cell its own neighbor or wrap past the whole lattice.
+    pub fn offsets_in(&self, shape: LatticeIdx) -> Result<Vec<LatticeIdx>, InitError> {
+        let check = |axis: usize, reach: i16| {
+            let extent = shape.axis(axis);
+            match reach.checked_abs() {
+                Some(reach) if reach < extent => Ok(()),
+                _ => Err(InitError::NeighborhoodTooLarge { axis, reach, extent }),
+            }
+        };
+
+        let radius = match self.stencil {
+            Stencil::VonNeumann(radius) | Stencil::Moore(radius) => radius,
+            Stencil::Custom(ref offsets) => {
+                let offsets: Vec<_> = offsets.iter()
+                    .copied()
+                    .filter(|&offset| (0..3).all(|axis| shape.axis(axis) > 1 || offset.axis(axis) == 0))
+                    .collect();
+                for offset in &offsets {
+                    for axis in shape.active_axes() {
+                        check(axis, offset.axis(axis))?;
+                    }
+                }
+                return Ok(offsets);
+            },
+        };
+
+        // Only the axes the shape extends along are searched, and every
+        // coordinate stays within the extent of its axis
+        let mut reach = [0i16; 3];
+        for axis in shape.active_axes() {
+            check(axis, radius)?;
+            reach[axis] = radius.max(0);
+        }
+        let mut offsets = Vec::new();
+        for x in -reach[0]..=reach[0] {
+            for y in -reach[1]..=reach[1] {
+                for z in -reach[2]..=reach[2] {
+                    let offset = LatticeIdx(x, y, z);
+                    let distance = x.unsigned_abs() as u32 + y.unsigned_abs() as u32 + z.unsigned_abs() as u32;
+                    let included = match self.stencil {
+                        Stencil::VonNeumann(radius) => distance <= radius as u32,
+                        _ => true,
+                    };
+                    if distance > 0 && included {
+                        offsets.push(offset);
+                    }
+                }
+            }
+        }
+        Ok(offsets)
+    }
+}
+
+impl Default for Neighborhood {
+    fn default() -> Self {
+        Self::von_neumann(1)
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+
+use crate::bone_lattice::{BoneLattice, State};
+
+/// A cell reached by an event of the simulation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Change {
+    /// Simulation time of the event.
+    pub time: f64,
+    /// The cell whose state spread to the target, or [`None`] if the target
+    /// changed state on its own.
+    pub source: Option<usize>,
+    pub target: usize,
+    /// State of the target before the event, which is the same as `new` for
+    /// invasions of a cell in the same state.
+    pub old: State,
+    pub new: State,
+}
+
+/// Measurements attached to a [`BoneLattice`] with
+/// [`BoneLattice::add_observer`], which are told about every event and can
+/// sample the lattice at regular intervals of simulation time. Observers are
+/// owned by the lattice, so results are best sent out through a channel or a
+/// shared handle such as `Arc<Mutex<_>>`.
+pub trait Observer: Send + Sync {
+    /// Called after every event that reaches a cell, with the lattice as it
+    /// is after the event. Events of a tau leap are reported in the order they
+    /// are applied, all at the end of the leap and with the lattice as it is
+    /// after the whole leap.
+    fn event(&mut self, _lattice: &BoneLattice, _change: &Change) {}
+
+    /// Called with the lattice as it was at `time`, for every multiple of
+    /// the observer's sampling interval after it was added.
+    fn sample(&mut self, _lattice: &BoneLattice, _time: f64) {}
+}
+
+/// An observer and when it next samples the lattice.
+struct Attached {
+    observer: Box<dyn Observer>,
+    interval: Option<f64>,
+    next_sample: f64,
+}
+
+/// The observers attached to a lattice. Clones of a lattice start without
+/// any, since observers cannot be cloned.
+#[derive(Default)]
+pub(crate) struct Observers {
+    attached: Vec<Attached>,
+}
+
+impl Observers {
+    pub fn is_empty(&self) -> bool {
+        self.attached.is_empty()
+    }
+
+    /// Adds an observer that samples every `interval` units of simulation
+    /// time, starting at `time`.
+    pub fn add(&mut self, observer: Box<dyn Observer>, interval: Option<f64>, time: f64) {
+        self.attached.push(Attached { observer, interval, next_sample: time });
+    }
+
+    /// Starts sampling over from `time`.
+    pub fn restart(&mut self, time: f64) {
+        for attached in &mut self.attached {
+            attached.next_sample = time;
+        }
+    }
+
+    /// Removes every observer, returning them in the order they were added.
+    pub fn take(&mut self) -> Vec<Box<dyn Observer>> {
+        self.attached.drain(..).map(|attached| attached.observer).collect()
+    }
+
+    pub fn event(&mut self, lattice: &BoneLattice, change: &Change) {
+        for attached in &mut self.attached {
+            attached.observer.event(lattice, change);
+        }
+    }
+
+    /// Takes every sample due before `time`, which should be when the lattice
+    /// next changes.
+    pub fn sample_until(&mut self, lattice: &BoneLattice, time: f64) {
+        for attached in &mut self.attached {
+            let Some(interval) = attached.interval else { continue };
+            while attached.next_sample < time {
+                attached.observer.sample(lattice, attached.next_sample);
+                attached.next_sample += interval;
+            }
+        }
+    }
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} observers", self.attached.len())
+    }
+}
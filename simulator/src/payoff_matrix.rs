@@ -1,26 +1,46 @@
-use crate::State;
+use std::fmt;
+
+use crate::bone_lattice::State;
+use crate::real::Real;
 
 /// 3x3 matrix that determines the fitness of each population in the presence of the other.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PayoffMatrix {
-    resorption: [f32; 3],
-    formation:  [f32; 3],
-    quiescence: [f32; 3],
+    resorption: [Real; 3],
+    formation:  [Real; 3],
+    quiescence: [Real; 3],
 }
 
 impl PayoffMatrix {
+    /// Creates the matrix from the payoffs of each population against each
+    /// other, failing if any payoff is infinite or NaN.
     pub fn new(
-        resorption: [f32; 3],
-        formation:  [f32; 3],
-        quiescence: [f32; 3],
-    ) -> Self {
-        Self { resorption, formation, quiescence }
+        resorption: [Real; 3],
+        formation:  [Real; 3],
+        quiescence: [Real; 3],
+    ) -> Result<Self, PayoffError> {
+        let matrix = Self { resorption, formation, quiescence };
+        for cell in State::ALL {
+            for against in State::ALL {
+                let value = matrix.get(cell, against);
+                if !value.is_finite() {
+                    return Err(PayoffError { cell, against, value });
+                }
+            }
+        }
+        Ok(matrix)
     }
 
-    pub fn by_params(alpha: [f32; 3], beta: [f32; 3]) -> PayoffMatrix {
+    /// Builds the matrix from the interaction parameters of each pair of
+    /// populations, with every entry `1 + omega * parameter`. The diagonal is
+    /// 1.
+    pub fn by_params(
+        alpha: [Real; 3],
+        beta: [Real; 3],
+        omega: Real,
+    ) -> Result<PayoffMatrix, PayoffError> {
 
         //let theta = 0.485;
-        let omega = 0.1;
 
         Self::new(
             [
@@ -41,7 +61,20 @@ impl PayoffMatrix {
         )
     }
 
-    pub fn get(&self, cell: State, against: State) -> f32 {
+    /// Gets the matrix `fraction` of the way from this one to `other`, entry
+    /// by entry.
+    pub fn interpolate(&self, other: &Self, fraction: Real) -> Self {
+        let lerp = |from: &[Real; 3], to: &[Real; 3]| {
+            std::array::from_fn(|i| from[i] + (to[i] - from[i]) * fraction)
+        };
+        Self {
+            resorption: lerp(&self.resorption, &other.resorption),
+            formation: lerp(&self.formation, &other.formation),
+            quiescence: lerp(&self.quiescence, &other.quiescence),
+        }
+    }
+
+    pub fn get(&self, cell: State, against: State) -> Real {
 
         let idx = match against {
             State::Resorption => 0,
@@ -61,3 +94,20 @@ impl PayoffMatrix {
         }
     }
 }
+
+/// A payoff that is infinite or NaN, and so cannot give a fitness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayoffError {
+    pub cell: State,
+    pub against: State,
+    pub value: Real,
+}
+
+impl fmt::Display for PayoffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the payoff of state {} against state {} is {}",
+            self.cell as u8, self.against as u8, self.value)
+    }
+}
+
+impl std::error::Error for PayoffError {}
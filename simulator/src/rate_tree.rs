@@ -0,0 +1,344 @@
+use crate::real::{narrow, widen, Real};
+
+/// Rates of a set of events stored in a Fenwick tree, so that changing a rate
+/// and choosing an event with probability proportional to its rate both take
+/// O(log N) time. Rates are only kept as the sums in the tree, and a rate is
+/// read back as the difference of two running sums.
+#[derive(Debug, Clone)]
+pub(crate) struct RateTree {
+    /// Fenwick tree of the rates, indexed from 1. Sums are kept in double
+    /// precision so that small rates are not lost next to large totals.
+    tree: Vec<f64>,
+    /// One bit per event for whether its rate is positive, so that rounding
+    /// in the sums never makes an event with rate 0 fire.
+    positive: Vec<u64>,
+    total: f64,
+    /// Number of events with a positive rate.
+    active: usize,
+    /// Updates since the tree was last rebuilt from the rates.
+    updates: usize,
+}
+
+impl RateTree {
+    /// Creates a tree of `len` events that all have rate 0.
+    pub fn new(len: usize) -> Self {
+        Self {
+            tree: vec![0.0; len + 1],
+            positive: vec![0; len.div_ceil(64)],
+            total: 0.0,
+            active: 0,
+            updates: 0,
+        }
+    }
+
+    /// Number of events.
+    pub fn len(&self) -> usize {
+        self.tree.len() - 1
+    }
+
+    /// Sum of all rates.
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    /// Gets the rate of an event. Each update rounds the sums it touches, so
+    /// this may be slightly off from the rate the event was set to.
+    pub fn rate(&self, event: usize) -> Real {
+        if !self.is_positive(event) { return 0.0 }
+        narrow(self.point(event))
+    }
+
+    /// Whether the tree holds an event at a rate, up to the rounding its
+    /// updates could have picked up since it was last rebuilt.
+    pub fn holds(&self, event: usize, rate: Real) -> bool {
+        let depth = (usize::BITS - self.len().leading_zeros()) as usize;
+        let tolerance = 2.0 * f64::EPSILON * self.total.abs() * (self.updates + depth + 1) as f64;
+        self.is_positive(event) == (rate > 0.0)
+            && (self.point(event) - widen(rate)).abs() <= tolerance
+    }
+
+    /// Number of events with a positive rate.
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Sets the rate of an event. Rates that are not finite or not positive
+    /// are kept as 0, so that they cannot spoil the sums of every other
+    /// event; the simulation reports them as errors instead.
+    pub fn set(&mut self, event: usize, rate: Real) {
+        let rate = valid(rate);
+        // Taking away the rate the tree holds rather than the one last set
+        // also takes away any rounding it has picked up
+        let delta = widen(rate) - self.point(event);
+        let was_positive = self.is_positive(event);
+        if delta == 0.0 && was_positive == (rate > 0.0) { return }
+        match (was_positive, rate > 0.0) {
+            (false, true) => self.active += 1,
+            (true, false) => self.active -= 1,
+            _ => {},
+        }
+        self.set_positive(event, rate > 0.0);
+        self.total += delta;
+
+        let mut i = event + 1;
+        while i < self.tree.len() {
+            self.tree[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+
+        // Adding differences slowly drifts the sums away from the rates, so
+        // start over from the rates once every event could have changed
+        self.updates += 1;
+        if self.updates >= self.len() {
+            self.rebuild();
+        }
+    }
+
+    /// Sets the rates of many events at once, rebuilding the tree afterwards
+    /// if that is cheaper than updating it for each event.
+    pub fn set_many<I: ExactSizeIterator<Item = (usize, Real)>>(&mut self, rates: I) {
+        let len = self.len();
+        let log_len = (usize::BITS - len.leading_zeros()) as usize;
+        if rates.len() * log_len < len {
+            for (event, rate) in rates {
+                self.set(event, rate);
+            }
+        } else {
+            self.unbuild();
+            for (event, rate) in rates {
+                let rate = valid(rate);
+                self.tree[event + 1] = widen(rate);
+                self.set_positive(event, rate > 0.0);
+            }
+            self.build();
+        }
+    }
+
+    /// Recomputes every sum from the rates in O(N) time.
+    pub fn rebuild(&mut self) {
+        self.unbuild();
+        self.build();
+    }
+
+    /// Finds the event at which the running sum of rates first exceeds
+    /// `target`, which should lie in `0..total`. Events with rate 0 are never
+    /// returned, so [`None`] means rounding pushed `target` past the end or
+    /// onto such an event.
+    pub fn find(&self, mut target: f64) -> Option<usize> {
+        let len = self.len();
+        if len == 0 { return None }
+
+        // Descend from the largest power of two that fits, skipping every
+        // block whose sum does not reach the target
+        let mut pos = 0;
+        let mut step = 1 << (usize::BITS - 1 - len.leading_zeros());
+        while step > 0 {
+            let next = pos + step;
+            if next < self.tree.len() && self.tree[next] <= target {
+                pos = next;
+                target -= self.tree[next];
+            }
+            step /= 2;
+        }
+
+        // pos is the number of events whose cumulative rate is at most target
+        (pos < len && self.is_positive(pos)).then_some(pos)
+    }
+
+    /// Gets the rate the sums hold for an event in O(log N) time, which is
+    /// the sum of its block minus the blocks nested in it.
+    fn point(&self, event: usize) -> f64 {
+        let i = event + 1;
+        let mut rate = self.tree[i];
+        let parent = i - (i & i.wrapping_neg());
+        let mut child = i - 1;
+        while child > parent {
+            rate -= self.tree[child];
+            child -= child & child.wrapping_neg();
+        }
+        rate
+    }
+
+    /// Turns the sums back into the rates of each event in O(N) time. Rates
+    /// are rounded to [`Real`], which drops the drift of small sums, and
+    /// events with rate 0 are made exactly 0.
+    fn unbuild(&mut self) {
+        for i in (1..self.tree.len()).rev() {
+            let parent = i + (i & i.wrapping_neg());
+            if parent < self.tree.len() {
+                self.tree[parent] -= self.tree[i];
+            }
+        }
+        for event in 0..self.len() {
+            self.tree[event + 1] = match self.is_positive(event) {
+                true => widen(narrow(self.tree[event + 1])),
+                false => 0.0,
+            };
+        }
+    }
+
+    /// Turns the rates of each event, as left by [`RateTree::unbuild`], into
+    /// sums in O(N) time.
+    fn build(&mut self) {
+        self.total = self.tree.iter().sum();
+        for i in 1..self.tree.len() {
+            let parent = i + (i & i.wrapping_neg());
+            if parent < self.tree.len() {
+                self.tree[parent] += self.tree[i];
+            }
+        }
+        self.active = self.positive.iter().map(|bits| bits.count_ones() as usize).sum();
+        self.updates = 0;
+    }
+
+    fn is_positive(&self, event: usize) -> bool {
+        self.positive[event / 64] >> (event % 64) & 1 == 1
+    }
+
+    fn set_positive(&mut self, event: usize, positive: bool) {
+        let bit = 1 << (event % 64);
+        match positive {
+            true => self.positive[event / 64] |= bit,
+            false => self.positive[event / 64] &= !bit,
+        }
+    }
+}
+
+/// Keeps a rate if it can go into the sums, or gives 0 otherwise.
+fn valid(rate: Real) -> Real {
+    match rate.is_finite() && rate > 0.0 {
+        true => rate,
+        false => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// Finds the event a target lands on by adding up the rates in order.
+    fn linear_find(rates: &[Real], target: f64) -> Option<usize> {
+        let mut sum = 0.0;
+        for (event, &rate) in rates.iter().enumerate() {
+            sum += widen(rate);
+            if sum > target && rate > 0.0 {
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    /// Rates where about a third of the events have rate 0.
+    fn random_rate(rng: &mut StdRng) -> Real {
+        match rng.gen_range(0..3) {
+            0 => 0.0,
+            _ => rng.gen_range(1..=8) as Real,
+        }
+    }
+
+    /// Checks the tree against the rates it should hold, at targets on and
+    /// between the running sums where rounding cannot change the answer.
+    fn check(tree: &RateTree, rates: &[Real]) {
+        assert_eq!(tree.len(), rates.len());
+        assert_eq!(tree.total(), rates.iter().map(|&rate| widen(rate)).sum::<f64>());
+        assert_eq!(tree.active(), rates.iter().filter(|&&rate| rate > 0.0).count());
+        for (event, &rate) in rates.iter().enumerate() {
+            assert_eq!(tree.rate(event), rate, "rate of event {}", event);
+        }
+
+        let mut sum = 0.0;
+        for &rate in rates {
+            for target in [sum, sum + 0.5] {
+                if target < tree.total() {
+                    assert_eq!(tree.find(target), linear_find(rates, target), "target {}", target);
+                }
+            }
+            sum += widen(rate);
+        }
+    }
+
+    #[test]
+    fn find_matches_linear_scan_after_set() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for len in [1, 2, 3, 7, 8, 9, 64, 65, 100] {
+            let mut tree = RateTree::new(len);
+            let mut rates = vec![0.0; len];
+            check(&tree, &rates);
+            for _ in 0..3 * len {
+                let event = rng.gen_range(0..len);
+                rates[event] = random_rate(&mut rng);
+                tree.set(event, rates[event]);
+                check(&tree, &rates);
+            }
+        }
+    }
+
+    #[test]
+    fn find_matches_linear_scan_after_set_many() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for len in [1, 5, 16, 33, 100] {
+            let mut tree = RateTree::new(len);
+            let mut rates = vec![0.0; len];
+            // Both few changes, which are set one by one, and many, which
+            // rebuild the tree
+            for count in [1, len / 4, len, 2 * len] {
+                let changes: Vec<_> = (0..count)
+                    .map(|_| (rng.gen_range(0..len), random_rate(&mut rng)))
+                    .collect();
+                for &(event, rate) in &changes {
+                    rates[event] = rate;
+                }
+                tree.set_many(changes.into_iter());
+                check(&tree, &rates);
+            }
+        }
+    }
+
+    #[test]
+    fn find_matches_linear_scan_after_rebuild() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let len = 50;
+        let mut tree = RateTree::new(len);
+        let mut rates = vec![0.0; len];
+        for _ in 0..10 {
+            for _ in 0..len / 2 {
+                let event = rng.gen_range(0..len);
+                rates[event] = random_rate(&mut rng);
+                tree.set(event, rates[event]);
+            }
+            tree.rebuild();
+            check(&tree, &rates);
+        }
+    }
+
+    #[test]
+    fn zero_rates_are_never_found() {
+        let mut tree = RateTree::new(4);
+        tree.set(1, 0.1);
+        tree.set(3, 0.2);
+        tree.set(1, 0.0);
+        for target in [0.0, 0.05, 0.1, 0.15, 0.2 - 1e-9] {
+            assert_eq!(tree.find(target), Some(3), "target {}", target);
+        }
+        tree.set(3, 0.0);
+        assert_eq!(tree.active(), 0);
+        assert_eq!(tree.find(0.0), None);
+    }
+
+    #[test]
+    fn invalid_rates_are_kept_as_zero() {
+        let mut tree = RateTree::new(8);
+        tree.set(0, 1.0);
+        for (event, rate) in [(1, Real::NAN), (2, Real::INFINITY), (3, -1.0)] {
+            tree.set(event, rate);
+        }
+        check(&tree, &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        tree.set_many([(4, Real::NAN), (5, Real::NEG_INFINITY), (6, 2.0), (7, -0.5)].into_iter());
+        tree.rebuild();
+        check(&tree, &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0]);
+    }
+}
@@ -0,0 +1,23 @@
+/// Floating point type of payoffs, fitness and rates. This is `f32` unless the
+/// `f64` feature is enabled, which doubles the memory they take but keeps
+/// small differences between large rates. Simulation time is always `f64`,
+/// since it adds up tiny waiting times over the whole run.
+#[cfg(not(feature = "f64"))]
+pub type Real = f32;
+
+/// Floating point type of payoffs, fitness and rates. This is `f64` since the
+/// `f64` feature is enabled.
+#[cfg(feature = "f64")]
+pub type Real = f64;
+
+/// Converts a [`Real`] to `f64`, which does nothing when they are the same.
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn widen(value: Real) -> f64 {
+    value as f64
+}
+
+/// Converts an `f64` to a [`Real`], rounding it unless they are the same.
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn narrow(value: f64) -> Real {
+    value as Real
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+
+use crate::lattice::{check_same_shape, Lattice, ShapeError};
+use crate::payoff_matrix::PayoffMatrix;
+use crate::topology::{Grid, Topology};
+
+/// Payoff matrices that differ across the simulation, such as between
+/// cortical and trabecular bone. Every node belongs to a numbered region.
+/// Region 0 plays by the lattice's own payoff matrix, which follows any
+/// payoff schedule, and every other region `n` by the `n`th matrix given.
+#[derive(Debug, Clone)]
+pub struct PayoffRegions {
+    /// Region of each node.
+    regions: Vec<u8>,
+    /// Matrix of each region after region 0.
+    matrices: Vec<PayoffMatrix>,
+}
+
+impl PayoffRegions {
+    /// Creates the regions from the region of each node, failing if a region
+    /// has no matrix.
+    pub fn new(regions: Vec<u8>, matrices: Vec<PayoffMatrix>) -> Result<Self, RegionError> {
+        let missing = regions.iter().enumerate()
+            .find(|&(_, &region)| region as usize > matrices.len());
+        if let Some((node, &region)) = missing {
+            return Err(RegionError::NoMatrix { node, region });
+        }
+        Ok(Self { regions, matrices })
+    }
+
+    /// Creates the regions from a map laid out like a lattice, which must have
+    /// the same shape.
+    pub fn from_lattice(
+        grid: &Grid,
+        map: &Lattice<u8>,
+        matrices: Vec<PayoffMatrix>,
+    ) -> Result<Self, RegionError> {
+        check_same_shape(grid.shape(), map.shape())?;
+        Self::new((0..grid.len()).map(|node| map[grid.idx(node)]).collect(), matrices)
+    }
+
+    /// Divides a lattice into layers by the depth of each cell along an axis,
+    /// which is how many cells it is from the nearer face. Cells are in the
+    /// region numbered by how many of `thresholds` their depth reaches, so
+    /// with thresholds 2 and 5, the two cells nearest each face are in
+    /// region 0, the next three in region 1 and the rest in region 2. There
+    /// can be at most 255 thresholds, since regions are numbered by a byte.
+    pub fn by_depth(
+        grid: &Grid,
+        axis: usize,
+        thresholds: &[i16],
+        matrices: Vec<PayoffMatrix>,
+    ) -> Result<Self, RegionError> {
+        if thresholds.len() > u8::MAX as usize {
+            return Err(RegionError::TooManyThresholds { count: thresholds.len() });
+        }
+        let extent = grid.shape().axis(axis);
+        let regions = (0..grid.len())
+            .map(|node| {
+                let coord = grid.idx(node).axis(axis);
+                let depth = coord.min(extent - 1 - coord);
+                thresholds.iter().filter(|&&threshold| depth >= threshold).count() as u8
+            })
+            .collect();
+        Self::new(regions, matrices)
+    }
+
+    /// Number of nodes the regions cover.
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Gets the region of a node.
+    pub fn region(&self, node: usize) -> u8 {
+        self.regions[node]
+    }
+
+    /// Gets the matrix of a node's region, or [`None`] for region 0.
+    pub fn matrix(&self, node: usize) -> Option<&PayoffMatrix> {
+        match self.regions[node] {
+            0 => None,
+            region => Some(&self.matrices[region as usize - 1]),
+        }
+    }
+}
+
+/// A reason [`PayoffRegions`] cannot be built or used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    /// The map does not have the shape of the lattice.
+    Shape(ShapeError),
+    /// A node is in a region with no matrix.
+    NoMatrix { node: usize, region: u8 },
+    /// The regions cover a different number of nodes than the simulation has.
+    Len { len: usize, expected: usize },
+    /// There are more depth thresholds than regions can be numbered by.
+    TooManyThresholds { count: usize },
+}
+
+impl From<ShapeError> for RegionError {
+    fn from(err: ShapeError) -> Self {
+        RegionError::Shape(err)
+    }
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionError::Shape(err) => write!(f, "invalid map: {}", err),
+            RegionError::NoMatrix { node, region } => {
+                write!(f, "node {} is in region {}, which has no payoff matrix", node, region)
+            },
+            RegionError::Len { len, expected } => {
+                write!(f, "regions cover {} nodes, but the simulation has {}", len, expected)
+            },
+            RegionError::TooManyThresholds { count } => {
+                write!(f, "there are {} depth thresholds, but there can be at most {}", count, u8::MAX)
+            },
+        }
+    }
+}
+
+impl std::error::Error for RegionError {}
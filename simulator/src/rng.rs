@@ -0,0 +1,4 @@
+/// The random number generator simulations are run with. Xoshiro256++ is
+/// fast, has a small state, and gives the same sequence for the same seed on
+/// every platform, so a run can be repeated exactly from its seed.
+pub type SimRng = rand_xoshiro::Xoshiro256PlusPlus;
@@ -0,0 +1,189 @@
+use std::fmt;
+
+use crate::payoff_matrix::PayoffMatrix;
+use crate::real::Real;
+
+/// Most pieces a schedule may be split into, since each one holds a matrix.
+pub const MAX_PIECES: usize = 1 << 20;
+
+/// How a [`PayoffSchedule`] moves from one keyframe to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Interpolation {
+    /// Each keyframe's matrix holds until the next keyframe.
+    #[default]
+    Step,
+    /// Approximates a linear ramp from one keyframe to the next by a
+    /// staircase: the ramp is split into even pieces at most `resolution`
+    /// long, each holding the matrix from its middle. Within a piece rates
+    /// are exact, but they lag or lead the ramp by up to half a piece.
+    Staircase { resolution: f64 },
+}
+
+/// Payoff matrices that change over simulation time, given as keyframes. The
+/// schedule is constant between its breakpoints, which the simulation stops
+/// at to change every rate before going on.
+///
+/// Times before the first keyframe take its matrix, and times after the last
+/// take that one's. A periodic schedule instead repeats from the first
+/// keyframe every period, ramping back to the first matrix at the end of each
+/// period under [`Interpolation::Staircase`].
+#[derive(Debug, Clone)]
+pub struct PayoffSchedule {
+    /// Start time and matrix of each piece of the first period, in order of
+    /// time. The first piece also covers all earlier times.
+    pieces: Vec<(f64, PayoffMatrix)>,
+    period: Option<f64>,
+}
+
+impl PayoffSchedule {
+    /// Builds a schedule from keyframes of a time and the matrix at that
+    /// time. Keyframe times must increase, and all fit within one period of
+    /// the first if the schedule is periodic.
+    pub fn new(
+        keyframes: Vec<(f64, PayoffMatrix)>,
+        interpolation: Interpolation,
+        period: Option<f64>,
+    ) -> Result<Self, ScheduleError> {
+        let Some(&(first, _)) = keyframes.first() else {
+            return Err(ScheduleError::Empty);
+        };
+        let mut previous = f64::NEG_INFINITY;
+        for &(time, _) in &keyframes {
+            if !(time.is_finite() && time > previous) {
+                return Err(ScheduleError::Unordered { time });
+            }
+            previous = time;
+        }
+        if let Some(period) = period {
+            if !(period > 0.0 && period.is_finite()) || previous >= first + period {
+                return Err(ScheduleError::Period { period });
+            }
+        }
+        if let Interpolation::Staircase { resolution } = interpolation {
+            if resolution.is_nan() || resolution <= 0.0 {
+                return Err(ScheduleError::Resolution { resolution });
+            }
+        }
+
+        let end = period.map(|period| (first + period, &keyframes[0].1));
+        let ramp_end = |i: usize| keyframes.get(i + 1).map(|&(time, _)| time).or(end.map(|(time, _)| time));
+        if let Interpolation::Staircase { resolution } = interpolation {
+            // Count before splitting, so that a tiny resolution fails instead
+            // of running out of memory
+            let count: f64 = (0..keyframes.len())
+                .map(|i| match ramp_end(i) {
+                    Some(stop) => ((stop - keyframes[i].0) / resolution).ceil().max(1.0),
+                    None => 1.0,
+                })
+                .sum();
+            if count > MAX_PIECES as f64 {
+                return Err(ScheduleError::TooManyPieces { resolution });
+            }
+        }
+
+        let mut pieces = Vec::with_capacity(keyframes.len());
+        for (i, (start, from)) in keyframes.iter().enumerate() {
+            let next = keyframes.get(i + 1).map(|(time, matrix)| (*time, matrix)).or(end);
+            match (interpolation, next) {
+                (Interpolation::Staircase { resolution }, Some((stop, to))) => {
+                    let len = stop - start;
+                    let count = (len / resolution).ceil().max(1.0) as usize;
+                    for k in 0..count {
+                        let fraction = (k as f64 + 0.5) / count as f64;
+                        pieces.push((
+                            start + len * k as f64 / count as f64,
+                            from.interpolate(to, fraction as Real),
+                        ));
+                    }
+                },
+                _ => pieces.push((*start, from.clone())),
+            }
+        }
+
+        Ok(Self { pieces, period })
+    }
+
+    pub fn period(&self) -> Option<f64> {
+        self.period
+    }
+
+    /// Gets the matrix in force at a time.
+    pub fn matrix_at(&self, time: f64) -> &PayoffMatrix {
+        self.matrix(self.piece_at(time))
+    }
+
+    /// Number of pieces in the schedule, or in each period of a periodic one.
+    pub(crate) fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// Gets the piece in force at a time. Pieces are numbered on from the
+    /// first through every period of a periodic schedule.
+    pub(crate) fn piece_at(&self, time: f64) -> usize {
+        let first = self.pieces[0].0;
+        let (repeats, time) = match self.period {
+            Some(period) if time >= first => {
+                let repeats = ((time - first) / period).floor();
+                (repeats as usize, time - repeats * period)
+            },
+            _ => (0, time),
+        };
+        let piece = self.pieces.partition_point(|&(start, _)| start <= time).saturating_sub(1);
+        repeats * self.len() + piece
+    }
+
+    /// Gets the time a piece starts, or [`None`] if the schedule ends before
+    /// it.
+    pub(crate) fn start(&self, piece: usize) -> Option<f64> {
+        let (repeats, piece) = (piece / self.len(), piece % self.len());
+        match self.period {
+            Some(period) => Some(self.pieces[piece].0 + repeats as f64 * period),
+            None if repeats == 0 => Some(self.pieces[piece].0),
+            None => None,
+        }
+    }
+
+    /// Gets the matrix of a piece, which should be one the schedule reaches.
+    pub(crate) fn matrix(&self, piece: usize) -> &PayoffMatrix {
+        &self.pieces[piece % self.len()].1
+    }
+}
+
+/// A reason a [`PayoffSchedule`] cannot be built.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleError {
+    /// There are no keyframes.
+    Empty,
+    /// A keyframe time is infinite or NaN, or not after the one before it.
+    Unordered { time: f64 },
+    /// The period is not positive and finite, or is too short to hold every
+    /// keyframe.
+    Period { period: f64 },
+    /// The resolution of a staircase is not positive.
+    Resolution { resolution: f64 },
+    /// The resolution of a staircase splits the schedule into more than
+    /// [`MAX_PIECES`] pieces.
+    TooManyPieces { resolution: f64 },
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::Empty => write!(f, "the schedule has no keyframes"),
+            ScheduleError::Unordered { time } => {
+                write!(f, "the keyframe at time {} is not after the one before it", time)
+            },
+            ScheduleError::Period { period } => {
+                write!(f, "a period of {} does not hold every keyframe", period)
+            },
+            ScheduleError::Resolution { resolution } => {
+                write!(f, "a ramp resolution of {} is not positive", resolution)
+            },
+            ScheduleError::TooManyPieces { resolution } => {
+                write!(f, "a ramp resolution of {} splits the schedule into more than {} pieces", resolution, MAX_PIECES)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
@@ -0,0 +1,93 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::bone_lattice::{BoneLattice, State};
+
+/// A condition that ends a run early.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopCondition {
+    /// Some state has no cells left in the domain.
+    Extinct,
+    /// Every cell of the domain is in the state.
+    Fixed(State),
+    /// At least `count` cells are in the state.
+    Above { state: State, count: usize },
+    /// At most `count` cells are in the state.
+    Below { state: State, count: usize },
+    /// Simulation time has reached this value.
+    Time(f64),
+    /// The run has taken this long in real time.
+    WallClock(Duration),
+    /// The fraction of the domain in each state has stayed within
+    /// `tolerance` of where it was at the start of the last `window` units of
+    /// simulation time. The window should be positive, or this holds before
+    /// any step is taken.
+    Stationary { tolerance: f64, window: f64 },
+}
+
+impl fmt::Display for StopCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopCondition::Extinct => write!(f, "a state went extinct"),
+            StopCondition::Fixed(state) => write!(f, "{} fixed", state),
+            StopCondition::Above { state, count } => write!(f, "{} reached {} cells", state, count),
+            StopCondition::Below { state, count } => write!(f, "{} fell to {} cells", state, count),
+            StopCondition::Time(time) => write!(f, "t reached {}", time),
+            StopCondition::WallClock(limit) => write!(f, "{}s of real time passed", limit.as_secs_f64()),
+            StopCondition::Stationary { tolerance, window } => {
+                write!(f, "counts stayed within {} for {} units of time", tolerance, window)
+            },
+        }
+    }
+}
+
+/// Watches a run for any of a set of [`StopCondition`]s.
+#[derive(Debug, Clone)]
+pub struct Stopper {
+    conditions: Vec<StopCondition>,
+    /// Time and counts at the start of the current window of each
+    /// stationarity condition.
+    anchors: Vec<Option<(f64, [usize; 3])>>,
+    started: Instant,
+}
+
+impl Stopper {
+    /// Starts watching for the conditions, with the wall clock starting now.
+    pub fn new(conditions: Vec<StopCondition>) -> Self {
+        let anchors = vec![None; conditions.len()];
+        Self { conditions, anchors, started: Instant::now() }
+    }
+
+    /// Checks the conditions against the lattice, which should be called
+    /// after every step, and returns the first that holds.
+    pub fn check(&mut self, lattice: &BoneLattice) -> Option<StopCondition> {
+        let counts = State::ALL.map(|state| lattice.count_of(state));
+        let domain_len = lattice.domain_len();
+
+        for (&condition, anchor) in self.conditions.iter().zip(&mut self.anchors) {
+            let met = match condition {
+                StopCondition::Extinct => counts.contains(&0),
+                StopCondition::Fixed(state) => counts[state as usize] == domain_len,
+                StopCondition::Above { state, count } => counts[state as usize] >= count,
+                StopCondition::Below { state, count } => counts[state as usize] <= count,
+                StopCondition::Time(time) => lattice.time >= time,
+                StopCondition::WallClock(limit) => self.started.elapsed() >= limit,
+                StopCondition::Stationary { tolerance, window } => {
+                    // Counts that move too far start a new window
+                    let (start, start_counts) = *anchor.get_or_insert((lattice.time, counts));
+                    let drifted = counts.iter().zip(start_counts).any(|(&count, start_count)| {
+                        count.abs_diff(start_count) as f64 > tolerance * domain_len as f64
+                    });
+                    if drifted {
+                        *anchor = Some((lattice.time, counts));
+                    }
+                    !drifted && lattice.time - start >= window
+                },
+            };
+            if met {
+                return Some(condition);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,391 @@
+use std::fmt;
+
+use crate::bone_lattice::{InitError, State};
+use crate::lattice::{Boundary, LatticeIdx, ShapeError, Site};
+use crate::layout::{IdxTable, Layout};
+use crate::neighborhood::Neighborhood;
+
+/// A neighbor of a node in a [`Topology`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Neighbor {
+    /// Another node of the topology.
+    Node(usize),
+    /// A boundary site that never changes state.
+    Fixed(State),
+    /// Nothing; invasions sent here are lost.
+    Void,
+}
+
+/// The spatial structure connecting the nodes of a simulation. Nodes are
+/// identified by ids from 0 up to [`Topology::len`], and each node has an
+/// ordered list of neighbors.
+pub trait Topology: fmt::Debug + Send + Sync {
+    /// Number of nodes.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of neighbors of a node, including boundary sites.
+    fn degree(&self, node: usize) -> usize;
+
+    /// Gets the `k`th neighbor of a node, where `k` is less than its degree.
+    fn neighbor(&self, node: usize, k: usize) -> Neighbor;
+
+    /// Calls `f` on every neighbor of a node in order. Implementations can
+    /// override this when looking up neighbors one at a time repeats work.
+    fn for_each_neighbor(&self, node: usize, f: &mut dyn FnMut(Neighbor)) {
+        for k in 0..self.degree(node) {
+            f(self.neighbor(node, k))
+        }
+    }
+
+    /// Calls `f` on every other node that has this node as a neighbor, once
+    /// for each time it does; these are the nodes affected when its state
+    /// changes. By default these are its neighbors, which is only right when
+    /// being neighbors is symmetric.
+    fn for_each_reverse_neighbor(&self, node: usize, f: &mut dyn FnMut(usize)) {
+        self.for_each_neighbor(node, &mut |neighbor| {
+            if let Neighbor::Node(other) = neighbor {
+                if other != node { f(other) }
+            }
+        })
+    }
+
+    /// Gets the lattice this topology is built from, if it is one.
+    fn as_grid(&self) -> Option<&Grid> {
+        None
+    }
+}
+
+/// A lattice where every cell has the same neighborhood, with boundary
+/// conditions applied at the edges. Node ids are the offsets of the cells in
+/// the grid's [`Layout`], so the layout decides how cells are ordered in
+/// memory by anything storing per-node data.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    shape: LatticeIdx,
+    boundaries: [Boundary<State>; 3],
+    neighborhood: Neighborhood,
+    /// Offsets of the neighborhood on this lattice.
+    offsets: Vec<LatticeIdx>,
+    layout: Layout,
+    /// Per-axis offsets that add up to the node at an index, if the layout
+    /// allows it; see [`Layout::axis_tables`].
+    axis_tables: Option<[Vec<usize>; 3]>,
+    /// Table finding the index of a node, if the layout is tiled and allows
+    /// it; see [`Layout::idx_table`].
+    idx_table: Option<IdxTable>,
+}
+
+impl Grid {
+    /// Creates a lattice with the specified extent along each axis, the
+    /// boundary condition of each axis and the neighborhood of every cell.
+    /// Offsets of the neighborhood along collapsed axes are dropped, and it is
+    /// an error if this leaves the neighborhood empty or if it reaches as far
+    /// as the extent of an axis; see [`Neighborhood::offsets_in`].
+    pub fn new(
+        shape: LatticeIdx,
+        boundaries: [Boundary<State>; 3],
+        neighborhood: Neighborhood,
+    ) -> Result<Self, InitError> {
+        shape.checked_volume()?;
+        let offsets = neighborhood.offsets_in(shape)?;
+        if offsets.is_empty() {
+            return Err(InitError::EmptyNeighborhood);
+        }
+        let layout = Layout::RowMajor;
+        let axis_tables = layout.axis_tables(shape);
+        Ok(Self { shape, boundaries, neighborhood, offsets, layout, axis_tables, idx_table: None })
+    }
+
+    /// Changes the order the cells are numbered in, which is row-major unless
+    /// set otherwise.
+    pub fn with_layout(self, layout: Layout) -> Result<Self, ShapeError> {
+        layout.check()?;
+        let axis_tables = layout.axis_tables(self.shape);
+        let idx_table = layout.idx_table(self.shape);
+        Ok(Self { layout, axis_tables, idx_table, ..self })
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Extent of the lattice along the x, y and z axes.
+    pub fn shape(&self) -> LatticeIdx {
+        self.shape
+    }
+
+    /// Number of axes the lattice extends along.
+    pub fn dimension(&self) -> usize {
+        self.shape.active_axes().count()
+    }
+
+    pub fn neighborhood(&self) -> &Neighborhood {
+        &self.neighborhood
+    }
+
+    /// Offsets from a cell to each of its neighbors.
+    pub fn offsets(&self) -> &[LatticeIdx] {
+        &self.offsets
+    }
+
+    /// Gets the node at an index, wrapping it around the lattice.
+    pub fn node(&self, idx: LatticeIdx) -> usize {
+        let shape = self.shape;
+        self.node_within(LatticeIdx(
+            idx.0.rem_euclid(shape.0),
+            idx.1.rem_euclid(shape.1),
+            idx.2.rem_euclid(shape.2),
+        ))
+    }
+
+    /// Gets the node at an index that is already inside the lattice.
+    fn node_within(&self, idx: LatticeIdx) -> usize {
+        match self.axis_tables {
+            Some([ref x, ref y, ref z]) => {
+                x[idx.0 as usize] + y[idx.1 as usize] + z[idx.2 as usize]
+            },
+            None => self.layout.offset(self.shape, idx),
+        }
+    }
+
+    /// Gets the index of a node.
+    pub fn idx(&self, node: usize) -> LatticeIdx {
+        match self.idx_table {
+            Some(ref table) => table.idx(node),
+            None => self.layout.idx(self.shape, node),
+        }
+    }
+
+    /// Coordinates along an axis from which `offset` leads to `target`. A
+    /// reflecting edge adds the coordinates whose step past either edge is
+    /// mirrored back onto the target.
+    fn sources_along(&self, axis: usize, target: i16, offset: i16) -> [Option<i16>; 3] {
+        let size = self.shape.axis(axis) as i32;
+        let (target, offset) = (target as i32, offset as i32);
+        let source = |coord: i32| (0..size).contains(&coord).then_some(coord as i16);
+        let direct = target - offset;
+        match self.boundaries[axis] {
+            Boundary::Periodic => [Some(direct.rem_euclid(size) as i16), None, None],
+            // Offsets are shorter than the axis, so a step past an edge is
+            // mirrored back at most once: from -1 - target below the lattice
+            // or from 2 * size - 1 - target above it
+            Boundary::Reflecting => [
+                source(direct),
+                source(-1 - target - offset),
+                source(2 * size - 1 - target - offset),
+            ],
+            _ => [source(direct), None, None],
+        }
+    }
+
+    fn resolve(&self, center: LatticeIdx, offset: LatticeIdx) -> Neighbor {
+        // Boundaries only matter for neighbors off the edge. Sums that
+        // overflow wrap to negative coordinates, which are off the edge too
+        let idx = LatticeIdx(
+            center.0.wrapping_add(offset.0),
+            center.1.wrapping_add(offset.1),
+            center.2.wrapping_add(offset.2),
+        );
+        if idx.within(self.shape) {
+            return Neighbor::Node(self.node_within(idx));
+        }
+
+        match LatticeIdx::resolve(center, offset, self.shape, &self.boundaries) {
+            Site::Cell(idx) => Neighbor::Node(self.node(idx)),
+            Site::Fixed(state) => Neighbor::Fixed(state),
+            Site::Void => Neighbor::Void,
+        }
+    }
+}
+
+impl Topology for Grid {
+    fn len(&self) -> usize {
+        self.shape.checked_volume().expect("shape is checked on creation")
+    }
+
+    fn degree(&self, _node: usize) -> usize {
+        self.offsets.len()
+    }
+
+    fn neighbor(&self, node: usize, k: usize) -> Neighbor {
+        self.resolve(self.idx(node), self.offsets[k])
+    }
+
+    fn for_each_neighbor(&self, node: usize, f: &mut dyn FnMut(Neighbor)) {
+        let center = self.idx(node);
+        for &offset in &self.offsets {
+            f(self.resolve(center, offset))
+        }
+    }
+
+    fn for_each_reverse_neighbor(&self, node: usize, f: &mut dyn FnMut(usize)) {
+        // A cell reaches the target by an offset when, along every axis, it
+        // lands on the target inside the lattice, across a periodic edge or
+        // mirrored back from a reflecting one; other boundaries never lead to
+        // another cell, and neighborhoods need not be symmetric
+        let target = self.idx(node);
+        for &offset in &self.offsets {
+            let sources = [0, 1, 2].map(|axis| self.sources_along(axis, target.axis(axis), offset.axis(axis)));
+            for &x in sources[0].iter().flatten() {
+                for &y in sources[1].iter().flatten() {
+                    for &z in sources[2].iter().flatten() {
+                        let idx = LatticeIdx(x, y, z);
+                        if idx != target {
+                            f(self.node_within(idx))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn as_grid(&self) -> Option<&Grid> {
+        Some(self)
+    }
+}
+
+/// An undirected graph stored as adjacency lists.
+#[derive(Debug, Clone)]
+pub struct Graph {
+    /// Where the neighbors of each node start in `neighbors`, with one extra
+    /// entry marking the end of the last node's neighbors.
+    starts: Vec<usize>,
+    neighbors: Vec<usize>,
+}
+
+impl Graph {
+    /// Builds a graph with `len` nodes from a list of edges between node ids.
+    /// Self-loops and repeated edges are ignored.
+    pub fn from_edges(len: usize, edges: &[(usize, usize)]) -> Result<Self, InitError> {
+        if len == 0 {
+            return Err(InitError::NoNodes);
+        }
+
+        let mut adjacency = vec![Vec::new(); len];
+        for &(a, b) in edges {
+            for node in [a, b] {
+                if node >= len {
+                    return Err(InitError::NodeOutOfRange { node, len });
+                }
+            }
+            if a == b { continue }
+            adjacency[a].push(b);
+            adjacency[b].push(a);
+        }
+
+        let mut starts = Vec::with_capacity(len + 1);
+        let mut neighbors = Vec::with_capacity(edges.len() * 2);
+        for mut list in adjacency {
+            list.sort_unstable();
+            list.dedup();
+            starts.push(neighbors.len());
+            neighbors.extend(list);
+        }
+        starts.push(neighbors.len());
+
+        Ok(Self { starts, neighbors })
+    }
+}
+
+impl Topology for Graph {
+    fn len(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    fn degree(&self, node: usize) -> usize {
+        self.starts[node + 1] - self.starts[node]
+    }
+
+    fn neighbor(&self, node: usize, k: usize) -> Neighbor {
+        Neighbor::Node(self.neighbors[self.starts[node] + k])
+    }
+
+    fn for_each_neighbor(&self, node: usize, f: &mut dyn FnMut(Neighbor)) {
+        for &neighbor in &self.neighbors[self.starts[node]..self.starts[node + 1]] {
+            f(Neighbor::Node(neighbor))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bone_lattice::State;
+
+    /// Inverts `for_each_neighbor` by brute force, listing for each node the
+    /// other nodes that have it as a neighbor, once for each time they do.
+    fn reverse_by_brute_force(grid: &Grid) -> Vec<Vec<usize>> {
+        let mut reverse = vec![Vec::new(); grid.len()];
+        for source in 0..grid.len() {
+            grid.for_each_neighbor(source, &mut |neighbor| {
+                if let Neighbor::Node(target) = neighbor {
+                    if target != source { reverse[target].push(source) }
+                }
+            });
+        }
+        reverse
+    }
+
+    #[test]
+    fn reverse_neighbors_invert_neighbors() {
+        let kinds = [
+            Boundary::Periodic,
+            Boundary::Reflecting,
+            Boundary::Fixed(State::Quiescence),
+            Boundary::Absorbing,
+        ];
+        let shapes = [
+            LatticeIdx(5, 1, 1),
+            LatticeIdx(4, 3, 1),
+            LatticeIdx(6, 6, 1),
+            LatticeIdx(3, 3, 3),
+            LatticeIdx(2, 5, 4),
+        ];
+        let neighborhoods = [
+            Neighborhood::von_neumann(1),
+            Neighborhood::von_neumann(2),
+            Neighborhood::moore(1),
+            Neighborhood::moore(2),
+            Neighborhood::custom(vec![LatticeIdx(1, 0, 0), LatticeIdx(2, 1, 0), LatticeIdx(0, -1, 1)]).unwrap(),
+        ];
+
+        let mut combinations = Vec::new();
+        for x in kinds {
+            for y in kinds {
+                for z in kinds {
+                    combinations.push([x, y, z]);
+                }
+            }
+        }
+
+        let mut checked = 0;
+        for shape in shapes {
+            for neighborhood in &neighborhoods {
+                for &boundaries in &combinations {
+                    // Neighborhoods too large for the shape are rejected
+                    let Ok(grid) = Grid::new(shape, boundaries, neighborhood.clone()) else { continue };
+                    for layout in [Layout::RowMajor, Layout::Tiled { edge: 2 }] {
+                        let grid = grid.clone().with_layout(layout).unwrap();
+                        let expected = reverse_by_brute_force(&grid);
+                        for (node, expected) in expected.into_iter().enumerate() {
+                            let mut reverse = Vec::new();
+                            grid.for_each_reverse_neighbor(node, &mut |other| reverse.push(other));
+                            reverse.sort_unstable();
+                            let mut expected = expected;
+                            expected.sort_unstable();
+                            assert_eq!(reverse, expected,
+                                "node {:?} of {:?} with {:?} and {:?}",
+                                grid.idx(node), shape, boundaries, neighborhood);
+                        }
+                        checked += 1;
+                    }
+                }
+            }
+        }
+        assert!(checked > 0);
+    }
+}
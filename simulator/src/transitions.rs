@@ -0,0 +1,50 @@
+use rand::Rng;
+
+use crate::bone_lattice::State;
+use crate::real::Real;
+
+/// Rates at which cells change from one state to another on their own,
+/// regardless of their neighbors. All rates start at 0.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TransitionRates {
+    /// Rate of each transition, indexed by the state before and after.
+    rates: [[Real; 3]; 3],
+}
+
+impl TransitionRates {
+    /// Gets the rate at which cells in state `from` change to `to`.
+    pub fn get(&self, from: State, to: State) -> Real {
+        self.rates[from as usize][to as usize]
+    }
+
+    /// Sets the rate at which cells in state `from` change to `to`. Rates from
+    /// a state to itself are ignored, since those transitions change nothing.
+    pub fn set(&mut self, from: State, to: State, rate: Real) {
+        if from != to {
+            self.rates[from as usize][to as usize] = rate;
+        }
+    }
+
+    /// Total rate at which cells in a state leave it.
+    pub fn total(&self, from: State) -> Real {
+        self.rates[from as usize].iter().sum()
+    }
+
+    /// Chooses the state a cell in state `from` changes to, with probability
+    /// proportional to the rate of each transition. Should only be called if
+    /// the state can be left.
+    pub fn choose<R: Rng + ?Sized>(&self, from: State, rng: &mut R) -> State {
+        let mut target = rng.gen::<Real>() * self.total(from);
+        let mut last = from;
+        for to in State::ALL {
+            let rate = self.get(from, to);
+            if rate <= 0.0 { continue }
+            if target < rate { return to }
+            target -= rate;
+            last = to;
+        }
+
+        // Rounding can leave the target just past the last rate
+        last
+    }
+}
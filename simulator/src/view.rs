@@ -0,0 +1,139 @@
+use crate::lattice::{check_same_shape, Lattice, LatticeIdx, ShapeError};
+
+/// A borrowed axis-aligned box inside a [`Lattice`]. Indices into the view are
+/// relative to its low corner, and the box wraps around the edges of the
+/// lattice just like indexing the lattice does. The low corner is wrapped into
+/// the lattice when the view is made.
+#[derive(Debug, Clone, Copy)]
+pub struct LatticeView<'a, T> {
+    lattice: &'a Lattice<T>,
+    low: LatticeIdx,
+    shape: LatticeIdx,
+}
+
+/// Mutable version of [`LatticeView`].
+#[derive(Debug)]
+pub struct LatticeViewMut<'a, T> {
+    lattice: &'a mut Lattice<T>,
+    low: LatticeIdx,
+    shape: LatticeIdx,
+}
+
+impl<T> Lattice<T> {
+    /// Borrows the box of the provided shape whose low corner is at `low`,
+    /// wrapped into the lattice. Fails if the box has a non-positive extent or
+    /// would wrap onto itself.
+    pub fn view(&self, low: LatticeIdx, shape: LatticeIdx)
+        -> Result<LatticeView<'_, T>, ShapeError>
+    {
+        check_view_shape(self.shape(), shape)?;
+        let low = low.offset_within(LatticeIdx(0, 0, 0), self.shape());
+        Ok(LatticeView { lattice: self, low, shape })
+    }
+
+    /// Mutable version of [`Lattice::view`].
+    pub fn view_mut(&mut self, low: LatticeIdx, shape: LatticeIdx)
+        -> Result<LatticeViewMut<'_, T>, ShapeError>
+    {
+        check_view_shape(self.shape(), shape)?;
+        let low = low.offset_within(LatticeIdx(0, 0, 0), self.shape());
+        Ok(LatticeViewMut { lattice: self, low, shape })
+    }
+}
+
+/// Checks that a view of the provided shape fits in a lattice without wrapping
+/// onto itself.
+pub(crate) fn check_view_shape(lattice: LatticeIdx, view: LatticeIdx) -> Result<(), ShapeError> {
+    view.checked_volume()?;
+    for axis in 0..3 {
+        if view.axis(axis) > lattice.axis(axis) {
+            return Err(ShapeError::LargerThanLattice {
+                axis,
+                extent: view.axis(axis) as i64,
+            });
+        }
+    }
+    Ok(())
+}
+
+impl<'a, T> LatticeView<'a, T> {
+    /// Index in the lattice of an index relative to the low corner.
+    fn at(&self, idx: LatticeIdx) -> LatticeIdx {
+        self.low.offset_within(idx, self.lattice.shape())
+    }
+
+    /// Index of the low corner of the box in the lattice.
+    pub fn low(&self) -> LatticeIdx {
+        self.low
+    }
+
+    /// Extent of the box along each axis.
+    pub fn shape(&self) -> LatticeIdx {
+        self.shape
+    }
+
+    /// Gets the value at an index relative to the low corner, or [`None`] if
+    /// it lies outside the box.
+    pub fn get(&self, idx: LatticeIdx) -> Option<&'a T> {
+        idx.within(self.shape).then(|| &self.lattice[self.at(idx)])
+    }
+
+    /// Iterates over the relative index and value of every cell in the box.
+    pub fn iter(&self) -> impl Iterator<Item = (LatticeIdx, &'a T)> + '_ {
+        LatticeIdx::shape_iter(self.shape)
+            .map(|idx| (idx, &self.lattice[self.at(idx)]))
+    }
+
+    /// Copies the box into a new lattice whose origin is the low corner.
+    pub fn to_lattice(&self) -> Lattice<T> where T: Clone {
+        self.map(T::clone)
+    }
+
+    /// Builds a new lattice by applying `f` to every value in the box.
+    pub fn map<U, F: FnMut(&T) -> U>(&self, mut f: F) -> Lattice<U> {
+        Lattice::filled(self.shape, |idx| f(&self.lattice[self.at(idx)]))
+            .expect("view shape is checked on creation")
+    }
+}
+
+impl<'a, T> LatticeViewMut<'a, T> {
+    /// Index in the lattice of an index relative to the low corner.
+    fn at(&self, idx: LatticeIdx) -> LatticeIdx {
+        self.low.offset_within(idx, self.lattice.shape())
+    }
+
+    /// Index of the low corner of the box in the lattice.
+    pub fn low(&self) -> LatticeIdx {
+        self.low
+    }
+
+    /// Extent of the box along each axis.
+    pub fn shape(&self) -> LatticeIdx {
+        self.shape
+    }
+
+    /// Gets the value at an index relative to the low corner, or [`None`] if
+    /// it lies outside the box.
+    pub fn get_mut(&mut self, idx: LatticeIdx) -> Option<&mut T> {
+        let idx = idx.within(self.shape).then(|| self.at(idx))?;
+        Some(&mut self.lattice[idx])
+    }
+
+    /// Reborrows the box immutably.
+    pub fn as_view(&self) -> LatticeView<'_, T> {
+        LatticeView { lattice: self.lattice, low: self.low, shape: self.shape }
+    }
+
+    /// Writes every value of `src` into the box. Fails if `src` does not have
+    /// the same shape as the box.
+    pub fn copy_from(&mut self, src: &Lattice<T>) -> Result<(), ShapeError>
+        where T: Clone
+    {
+        check_same_shape(self.shape, src.shape())?;
+        for idx in LatticeIdx::shape_iter(self.shape) {
+            let at = self.at(idx);
+            self.lattice[at] = src[idx].clone();
+        }
+        Ok(())
+    }
+}